@@ -0,0 +1,193 @@
+//! An assemble-and-run scratchpad: each line typed is assembled with
+//! `asm_one`, stored at the PC and executed straight away, then the
+//! registers are printed. `reset` starts over, `quit` leaves.
+//!
+//!     cargo run --example repl
+
+use std::io::{self, BufRead, Write};
+
+use lc3_vm::{asm_one, LineInput, StepResult, GENERAL_REGISTERS, VM};
+
+/* where the programs print; called again for each fresh VM */
+type Output = dyn Fn() -> Box<dyn Write>;
+
+fn main() -> io::Result<()> {
+    let output: &Output = &|| Box::new(io::stdout());
+    let mut vm = new_vm(output);
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    let mut line = String::new();
+
+    loop {
+        write!(stdout, "x{:04X}> ", vm.pc())?;
+        stdout.flush()?;
+
+        line.clear();
+        if stdin.lock().read_line(&mut line)? == 0 || !eval(&mut vm, &line, &mut stdout, output)? {
+            return Ok(());
+        }
+    }
+}
+
+/* the keyboard is the REPL's own stdin, so GETC and IN get nothing */
+fn new_vm(output: &Output) -> VM {
+    let mut vm = VM::new();
+    vm.set_input(LineInput::new(io::empty()));
+    vm.set_output(output());
+    vm
+}
+
+/* handles one line, reporting on `out`; false once the user asked to quit */
+fn eval(vm: &mut VM, line: &str, out: &mut dyn Write, output: &Output) -> io::Result<bool> {
+    match line.trim() {
+        "" => return Ok(true),
+        "quit" => return Ok(false),
+        "reset" => {
+            *vm = new_vm(output);
+            return Ok(true);
+        }
+        _ => {}
+    }
+
+    let word = match asm_one(line) {
+        Ok(word) => word,
+        Err(err) => {
+            writeln!(out, "error: {}", err)?;
+            return Ok(true);
+        }
+    };
+
+    let pc = vm.pc();
+    if let Err(err) = vm.mem_write(pc, word) {
+        writeln!(out, "error: {}", err)?;
+        return Ok(true);
+    }
+    // main's programs print to stdout; make sure it lands before the registers
+    let result = vm.step();
+    io::stdout().flush()?;
+
+    match result {
+        Ok(StepResult::Halted) => writeln!(out, "halted")?,
+        Ok(_) => {}
+        Err(err) => writeln!(out, "error: {}", err)?,
+    }
+
+    let registers: Vec<String> = GENERAL_REGISTERS
+        .iter()
+        .map(|&reg| format!("{:?}=x{:04X}", reg, vm.get_register(reg)))
+        .collect();
+    writeln!(out, "x{:04X}  {}", word, registers.join(" "))?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /* what the programs printed, shared with every VM of a session */
+    #[derive(Clone, Default)]
+    struct Printed(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Printed {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /* feeds `lines` to one session and returns what it reported, whether
+    it is still running, and what the programs printed */
+    fn session(lines: &[&str]) -> (String, bool, String) {
+        let printed = Printed::default();
+        let sink = printed.clone();
+        let output = move || Box::new(sink.clone()) as Box<dyn Write>;
+        let mut vm = new_vm(&output);
+        let mut out = Vec::new();
+        let mut running = true;
+        for line in lines {
+            running = eval(&mut vm, line, &mut out, &output).unwrap();
+            if !running {
+                break;
+            }
+        }
+        let printed = printed.0.borrow().clone();
+        (
+            String::from_utf8(out).unwrap(),
+            running,
+            String::from_utf8(printed).unwrap(),
+        )
+    }
+
+    #[test]
+    fn each_line_runs_and_prints_the_registers() {
+        let (out, running, _) = session(&["ADD R0, R0, #5", "", "ADD R1, R0, #-1"]);
+
+        assert!(running);
+        assert_eq!(
+            out,
+            "x1025  R0=x0005 R1=x0000 R2=x0000 R3=x0000 R4=x0000 R5=x0000 R6=x0000 R7=x0000\n\
+             x123F  R0=x0005 R1=x0004 R2=x0000 R3=x0000 R4=x0000 R5=x0000 R6=x0000 R7=x0000\n"
+        );
+    }
+
+    #[test]
+    fn assembly_errors_are_reported_and_the_session_goes_on() {
+        let (out, running, _) = session(&["MUL R0, R0, R0", "ADD R0, R0, #99", "ADD R2, R2, #1"]);
+
+        assert!(running);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "error: unknown mnemonic \"MUL\"");
+        assert_eq!(lines[1], "error: #99 does not fit in 5 bits");
+        assert!(lines[2].starts_with("x14A1  R0=x0000 R1=x0000 R2=x0001"));
+    }
+
+    #[test]
+    fn reset_starts_over_and_quit_stops() {
+        let (out, running, _) = session(&[
+            "ADD R0, R0, #1",
+            "reset",
+            "ADD R0, R0, #2",
+            "quit",
+            "ADD R0, R0, #3",
+        ]);
+
+        assert!(!running);
+        assert!(out.lines().nth(1).unwrap().contains("R0=x0002"));
+        assert_eq!(out.lines().count(), 2);
+    }
+
+    #[test]
+    fn halt_is_reported() {
+        let (out, _, printed) = session(&["HALT"]);
+        assert!(out.starts_with("halted\nxF025  "));
+        assert_eq!(printed, "HALT\n");
+    }
+
+    #[test]
+    fn what_a_program_prints_goes_to_the_vm_output() {
+        let (_, _, printed) = session(&[
+            "ADD R0, R0, #15",
+            "ADD R0, R0, #15",
+            "ADD R0, R0, #15",
+            "ADD R0, R0, #15",
+            "ADD R0, R0, #5",
+            "OUT",
+            "reset",
+            "ADD R0, R0, #15",
+            "ADD R0, R0, #15",
+            "ADD R0, R0, #15",
+            "ADD R0, R0, #15",
+            "ADD R0, R0, #6",
+            "OUT",
+        ]);
+
+        assert_eq!(printed, "AB");
+    }
+}
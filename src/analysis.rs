@@ -0,0 +1,206 @@
+use crate::disasm::looks_like_data;
+use crate::instruction::{decode, pc_relative, Instruction, OpCode, TrapCode};
+use crate::vm::VM;
+
+/// Something in a loaded image that would likely misbehave if executed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DecodeWarning {
+    /// The word decodes to the reserved opcode (1101).
+    ReservedOpcode,
+    /// The word decodes to RTI, which the VM does not implement.
+    Rti,
+    /// A BR or JSR whose target lies outside every loaded segment.
+    BranchOutOfRange { target: u16 },
+    /// A TRAP with a vector the VM has no routine for.
+    UnknownTrap(u8),
+}
+
+impl VM {
+    /// Statically checks every loaded word, in segment order, without
+    /// executing anything. Data words are checked too, so some warnings
+    /// will be about `.FILL`/`.STRINGZ` contents rather than code.
+    pub fn validate_image(&self) -> Vec<(u16, DecodeWarning)> {
+        let mut warnings = Vec::new();
+
+        for segment in self.segments() {
+            for address in segment.addresses() {
+                if let Some(warning) = self.check_word(address) {
+                    warnings.push((address, warning));
+                }
+            }
+        }
+
+        warnings
+    }
+
+    /// First address at or after `from` whose word decodes to `op`, wrapping
+    /// past xFFFF and scanning every address once. Memory holds no marker
+    /// of what is code, so data words that happen to match count too.
+    pub fn find_next(&self, from: u16, op: OpCode) -> Option<u16> {
+        (0..=u16::MAX)
+            .map(|offset| from.wrapping_add(offset))
+            .find(|&address| OpCode::from_instruction(self.peek(address)) == op)
+    }
+
+    /// Addresses from `start` to `end` inclusive whose word is `TRAP x25`.
+    /// Any word with that encoding counts, so a `.FILL xF025` in data is
+    /// reported too.
+    pub fn find_halts(&self, start: u16, end: u16) -> Vec<u16> {
+        (start..=end)
+            .filter(|&address| {
+                matches!(decode(self.peek(address)), Instruction::Trap { vector } if vector == TrapCode::Halt as u8)
+            })
+            .collect()
+    }
+
+    /// Addresses from `start` to `end` inclusive holding what looks like
+    /// code but were never executed. Needs `track_coverage` on during the
+    /// run. Data is told apart only by guesswork: words `looks_like_data`
+    /// rejects and TRAPs with unknown vectors (such as `.FILL xFFFF`) are
+    /// skipped, but data that happens to decode cleanly is still reported.
+    pub fn unreached_code(&self, start: u16, end: u16) -> Vec<u16> {
+        (start..=end)
+            .filter(|&address| !self.executed_addresses().contains(address))
+            .filter(|&address| {
+                let word = self.peek(address);
+                match decode(word) {
+                    Instruction::Trap { vector } => TrapCode::try_from(vector as u16).is_ok(),
+                    _ => !looks_like_data(word),
+                }
+            })
+            .collect()
+    }
+
+    fn check_word(&self, address: u16) -> Option<DecodeWarning> {
+        let target = match decode(self.peek(address)) {
+            Instruction::Res => return Some(DecodeWarning::ReservedOpcode),
+            Instruction::Rti => return Some(DecodeWarning::Rti),
+            Instruction::Trap { vector } => {
+                return TrapCode::try_from(vector as u16)
+                    .err()
+                    .map(|_| DecodeWarning::UnknownTrap(vector));
+            }
+            /* BR with no condition bits never branches */
+            Instruction::Br { cond: 0, .. } => return None,
+            Instruction::Br { offset, .. } | Instruction::Jsr { offset } => {
+                pc_relative(address, offset)
+            }
+            _ => return None,
+        };
+
+        let in_range = self.segments().iter().any(|s| s.contains(target));
+        (!in_range).then_some(DecodeWarning::BranchOutOfRange { target })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{Options, DEFAULT_ORIGIN};
+
+    fn loaded(words: &[u16]) -> VM {
+        let mut vm = VM::new();
+        vm.load_words(DEFAULT_ORIGIN, words);
+        vm
+    }
+
+    #[test]
+    fn validate_reports_a_reserved_opcode_at_its_address() {
+        let vm = loaded(&[0x1021, 0x1021, 0xD000, 0xF025]);
+
+        assert_eq!(
+            vm.validate_image(),
+            [(0x3002, DecodeWarning::ReservedOpcode)]
+        );
+    }
+
+    #[test]
+    fn validate_flags_rti_unknown_traps_and_wild_branches() {
+        let vm = loaded(&[
+            0x8000, // x3000 RTI
+            0xF0FF, // x3001 TRAP xFF
+            0x0E40, // x3002 BRnzp x3043, past the segment
+            0x0FFC, // x3003 BRnzp x3000
+            0x0000, // x3004 NOP, never taken
+            0xF025, // x3005 HALT
+        ]);
+
+        assert_eq!(
+            vm.validate_image(),
+            [
+                (0x3000, DecodeWarning::Rti),
+                (0x3001, DecodeWarning::UnknownTrap(0xFF)),
+                (0x3002, DecodeWarning::BranchOutOfRange { target: 0x3043 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_does_not_touch_the_vm() {
+        let vm = loaded(&[0xD000]);
+        let before = vm.snapshot();
+
+        vm.validate_image();
+        assert_eq!(vm.snapshot(), before);
+    }
+
+    #[test]
+    fn find_next_locates_the_next_matching_opcode() {
+        let vm = loaded(&[0x1021, 0x1021, 0xF021, 0x1021, 0xF025]);
+
+        assert_eq!(vm.find_next(0x3000, OpCode::Trap), Some(0x3002));
+        assert_eq!(vm.find_next(0x3003, OpCode::Trap), Some(0x3004));
+        assert_eq!(vm.find_next(0x3000, OpCode::Add), Some(0x3000));
+    }
+
+    #[test]
+    fn find_next_wraps_past_xffff() {
+        let mut vm = loaded(&[0xF025]);
+        vm.load_words(0xFFFF, &[0x1021]);
+
+        assert_eq!(vm.find_next(0x3001, OpCode::Trap), Some(0x3000));
+        /* zeroed memory is all BR, so scanning from anywhere finds one */
+        assert_eq!(vm.find_next(0xFFFF, OpCode::Br), Some(0x0000));
+        assert_eq!(vm.find_next(0x3001, OpCode::Lea), None);
+    }
+
+    #[test]
+    fn code_a_branch_skipped_is_reported_unreached() {
+        let mut vm = VM::with_options(Options {
+            track_coverage: true,
+            ..Options::default()
+        });
+        vm.set_output(std::io::sink());
+        vm.load_words(
+            DEFAULT_ORIGIN,
+            &[
+                0x5020, // x3000 AND R0, R0, #0
+                0x0402, // x3001 BRz #2
+                0x1021, // x3002 ADD R0, R0, #1
+                0x1022, // x3003 ADD R0, R0, #2
+                0xF025, // x3004 HALT
+                0x0041, // x3005 .FILL 'A'
+                0xFFFF, // x3006 .FILL xFFFF
+            ],
+        );
+        vm.run().unwrap();
+
+        assert_eq!(vm.unreached_code(0x3000, 0x3006), [0x3002, 0x3003]);
+    }
+
+    #[test]
+    fn find_halts_reports_only_trap_x25() {
+        let vm = loaded(&[
+            0xE003, // x3000 LEA R0, MSG
+            0xF022, // x3001 PUTS
+            0xF021, // x3002 OUT
+            0xF025, // x3003 HALT
+            0x0048, // x3004 MSG: 'H'
+            0x0000,
+        ]);
+
+        assert_eq!(vm.find_halts(0x3000, 0x3005), [0x3003]);
+        assert!(vm.find_halts(0x3000, 0x3002).is_empty());
+        assert_eq!(vm.find_halts(0x3003, 0x3003), [0x3003]);
+    }
+}
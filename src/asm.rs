@@ -0,0 +1,266 @@
+//! Assembling single instruction lines, for quick tests and the REPL.
+//! There are no labels: PC offsets are written as numbers, e.g.
+//! `BRnz #-3`, relative to the incremented PC as in `encode`.
+
+use crate::encode::*;
+use crate::error::AsmError;
+use crate::instruction::{Register, TrapCode, GENERAL_REGISTERS};
+
+/// Encodes one line such as `"ADD R0, R1, #5"`. Mnemonics and registers
+/// are case-insensitive; numbers are `#12`, `#-3`, `x1F`, `0x1F` or `12`.
+/// A `;` starts a comment.
+pub fn asm_one(line: &str) -> Result<u16, AsmError> {
+    let code = line.split(';').next().unwrap_or("").trim();
+    let (mnemonic, rest) = code.split_once(char::is_whitespace).unwrap_or((code, ""));
+    let operands: Vec<&str> = if rest.trim().is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',').map(str::trim).collect()
+    };
+    let mnemonic = mnemonic.to_ascii_uppercase();
+
+    let count = |expected: usize| {
+        if operands.len() == expected {
+            Ok(())
+        } else {
+            Err(AsmError::OperandCount {
+                expected,
+                found: operands.len(),
+            })
+        }
+    };
+    let reg = |index: usize| register(operands[index]);
+    let num = |index: usize, bits: u8| signed(operands[index], bits);
+
+    let word = match mnemonic.as_str() {
+        "ADD" | "AND" => {
+            count(3)?;
+            let (dr, sr1) = (reg(0)?, reg(1)?);
+            match (register(operands[2]), mnemonic.as_str()) {
+                (Ok(sr2), "ADD") => encode_add(dr, sr1, sr2),
+                (Ok(sr2), _) => encode_and(dr, sr1, sr2),
+                (Err(_), "ADD") => encode_add_imm(dr, sr1, num(2, 5)?),
+                (Err(_), _) => encode_and_imm(dr, sr1, num(2, 5)?),
+            }
+        }
+        "NOT" => {
+            count(2)?;
+            encode_not(reg(0)?, reg(1)?)
+        }
+        "LD" | "LDI" | "LEA" | "ST" | "STI" => {
+            count(2)?;
+            let (r, offset) = (reg(0)?, num(1, 9)?);
+            match mnemonic.as_str() {
+                "LD" => encode_ld(r, offset),
+                "LDI" => encode_ldi(r, offset),
+                "LEA" => encode_lea(r, offset),
+                "ST" => encode_st(r, offset),
+                _ => encode_sti(r, offset),
+            }
+        }
+        "LDR" | "STR" => {
+            count(3)?;
+            let (r, base, offset) = (reg(0)?, reg(1)?, num(2, 6)?);
+            if mnemonic == "LDR" {
+                encode_ldr(r, base, offset)
+            } else {
+                encode_str(r, base, offset)
+            }
+        }
+        "JMP" => {
+            count(1)?;
+            encode_jmp(reg(0)?)
+        }
+        "JSRR" => {
+            count(1)?;
+            encode_jsrr(reg(0)?)
+        }
+        "JSR" => {
+            count(1)?;
+            encode_jsr(num(0, 11)?)
+        }
+        "RET" => {
+            count(0)?;
+            encode_ret()
+        }
+        "RTI" => {
+            count(0)?;
+            encode_rti()
+        }
+        "TRAP" => {
+            count(1)?;
+            let vector = number(operands[0])?;
+            let vector = u8::try_from(vector).map_err(|_| AsmError::OutOfRange {
+                operand: operands[0].to_string(),
+                bits: 8,
+            })?;
+            encode_trap(vector)
+        }
+        _ => {
+            if let Some(trap) = trap_alias(&mnemonic) {
+                count(0)?;
+                encode_trap(trap as u8)
+            } else if let Some(flags) = mnemonic.strip_prefix("BR") {
+                let (n, z, p) =
+                    branch_flags(flags).ok_or(AsmError::UnknownMnemonic(mnemonic.clone()))?;
+                count(1)?;
+                encode_br(n, z, p, num(0, 9)?)
+            } else {
+                return Err(AsmError::UnknownMnemonic(mnemonic));
+            }
+        }
+    };
+
+    Ok(word)
+}
+
+fn trap_alias(mnemonic: &str) -> Option<TrapCode> {
+    match mnemonic {
+        "GETC" => Some(TrapCode::Getc),
+        "OUT" => Some(TrapCode::Out),
+        "PUTS" => Some(TrapCode::Puts),
+        "IN" => Some(TrapCode::In),
+        "PUTSP" => Some(TrapCode::Putsp),
+        "HALT" => Some(TrapCode::Halt),
+        _ => None,
+    }
+}
+
+/* the flag letters after BR, in n z p order; none at all means all three */
+fn branch_flags(flags: &str) -> Option<(bool, bool, bool)> {
+    if flags.is_empty() {
+        return Some((true, true, true));
+    }
+
+    let mut rest = flags;
+    let mut take = |letter: char| match rest.strip_prefix(letter) {
+        Some(tail) => {
+            rest = tail;
+            true
+        }
+        None => false,
+    };
+    let (n, z, p) = (take('N'), take('Z'), take('P'));
+
+    rest.is_empty().then_some((n, z, p))
+}
+
+fn register(token: &str) -> Result<Register, AsmError> {
+    let digit = token
+        .strip_prefix(['R', 'r'])
+        .and_then(|n| n.parse::<usize>().ok());
+    digit
+        .and_then(|n| GENERAL_REGISTERS.get(n).copied())
+        .ok_or_else(|| AsmError::BadOperand(token.to_string()))
+}
+
+/* a number that must fit a `bits`-wide two's complement field */
+fn signed(token: &str, bits: u8) -> Result<i16, AsmError> {
+    let value = number(token)?;
+    let limit = 1i32 << (bits - 1);
+
+    if (-limit..limit).contains(&value) {
+        Ok(value as i16)
+    } else {
+        Err(AsmError::OutOfRange {
+            operand: token.to_string(),
+            bits,
+        })
+    }
+}
+
+fn number(token: &str) -> Result<i32, AsmError> {
+    let bad = || AsmError::BadOperand(token.to_string());
+
+    let parsed = if let Some(decimal) = token.strip_prefix('#') {
+        decimal.parse()
+    } else if let Some(hex) = token
+        .strip_prefix("0x")
+        .or_else(|| token.strip_prefix(['x', 'X']))
+    {
+        match hex.strip_prefix('-') {
+            Some(magnitude) => i32::from_str_radix(magnitude, 16).map(|v| -v),
+            None => i32::from_str_radix(hex, 16),
+        }
+    } else {
+        token.parse()
+    };
+
+    parsed.map_err(|_| bad())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_single_lines_to_known_words() {
+        let cases = [
+            ("ADD R0, R1, #5", 0x1065),
+            ("add r0, r1, xF", 0x106F),
+            ("AND R2, R3, R4", 0x54C4),
+            ("AND R2, R3, #0", 0x54E0),
+            ("NOT R1, R2", 0x92BF),
+            ("LD R0, #-1", 0x21FF),
+            ("LEA R0, 0x2", 0xE002),
+            ("LDR R1, R6, #-1", 0x63BF),
+            ("STR R1, R6, #31", 0x739F),
+            ("BRnz #-3", 0x0DFD),
+            ("BR #0", 0x0E00),
+            ("JMP R3", 0xC0C0),
+            ("RET", 0xC1C0),
+            ("JSR #-1", 0x4FFF),
+            ("JSRR R2", 0x4080),
+            ("TRAP x23", 0xF023),
+            ("HALT ; done", 0xF025),
+        ];
+
+        for (line, word) in cases {
+            assert_eq!(asm_one(line), Ok(word), "{}", line);
+        }
+    }
+
+    #[test]
+    fn malformed_lines_say_what_is_wrong() {
+        assert_eq!(
+            asm_one("ADD R0, R1"),
+            Err(AsmError::OperandCount {
+                expected: 3,
+                found: 2
+            })
+        );
+        assert_eq!(
+            asm_one("ADD R0, R8, #1"),
+            Err(AsmError::BadOperand("R8".to_string()))
+        );
+        assert_eq!(
+            asm_one("ADD R0, R0, #16"),
+            Err(AsmError::OutOfRange {
+                operand: "#16".to_string(),
+                bits: 5
+            })
+        );
+        assert_eq!(
+            asm_one("TRAP x100"),
+            Err(AsmError::OutOfRange {
+                operand: "x100".to_string(),
+                bits: 8
+            })
+        );
+        assert_eq!(
+            asm_one("RET R7"),
+            Err(AsmError::OperandCount {
+                expected: 0,
+                found: 1
+            })
+        );
+        assert_eq!(
+            asm_one("brx #1"),
+            Err(AsmError::UnknownMnemonic("BRX".to_string()))
+        );
+        assert_eq!(
+            asm_one("MUL R0, R0, R0"),
+            Err(AsmError::UnknownMnemonic("MUL".to_string()))
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use crate::vm::MEMORY_MAX;
+
+const WORDS: usize = MEMORY_MAX / 64;
+
+/// One bit per LC-3 address.
+#[derive(Clone, PartialEq, Eq)]
+pub struct BitSet {
+    bits: Box<[u64; WORDS]>,
+}
+
+impl Default for BitSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BitSet {
+    pub fn new() -> Self {
+        Self {
+            bits: Box::new([0; WORDS]),
+        }
+    }
+
+    pub fn insert(&mut self, address: u16) {
+        self.bits[address as usize / 64] |= 1 << (address % 64);
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        self.bits[address as usize / 64] >> (address % 64) & 1 == 1
+    }
+
+    pub fn clear(&mut self) {
+        self.bits.fill(0);
+    }
+
+    pub fn len(&self) -> usize {
+        self.bits
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|&word| word == 0)
+    }
+
+    /// Set addresses in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..MEMORY_MAX)
+            .filter(|&address| self.contains(address as u16))
+            .map(|address| address as u16)
+    }
+}
+
+impl std::fmt::Debug for BitSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,179 @@
+//! Parsed-image caching for harnesses that load the same large images
+//! over and over. Enabled by the `cache` feature.
+
+use std::fs;
+use std::time::UNIX_EPOCH;
+
+use bincode::{Decode, Encode};
+use log::{debug, warn};
+
+use crate::error::LoadError;
+use crate::vm::{parse_image_file, VM};
+
+/* what `<image>.cache` holds: the parsed image and the source it came from */
+#[derive(Encode, Decode)]
+struct CachedImage {
+    /* source modification time, as nanoseconds since the epoch */
+    modified: u128,
+    origin: u16,
+    words: Vec<u16>,
+}
+
+impl VM {
+    /// Like `read_image` (gzip included), but keeps the parsed image in
+    /// `<filename>.cache` and loads from there while the source's
+    /// modification time is unchanged. A missing, stale or unreadable cache
+    /// just means parsing the source again; failing to write one is only
+    /// logged.
+    pub fn read_image_cached(&mut self, filename: &str) -> Result<u16, LoadError> {
+        let modified = fs::metadata(filename)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |age| age.as_nanos());
+        let cache_path = format!("{}.cache", filename);
+
+        if let Some(cached) = read_cache(&cache_path).filter(|cached| cached.modified == modified) {
+            debug!("{} loaded from {}", filename, cache_path);
+            self.load_image_words(cached.origin, &cached.words)?;
+            return Ok(cached.origin);
+        }
+
+        let (origin, words) = parse_image_file(fs::read(filename)?)?;
+        self.load_image_words(origin, &words)?;
+
+        let cached = CachedImage {
+            modified,
+            origin,
+            words,
+        };
+        let written = bincode::encode_to_vec(&cached, bincode::config::standard())
+            .map_err(|err| err.to_string())
+            .and_then(|encoded| fs::write(&cache_path, encoded).map_err(|err| err.to_string()));
+        if let Err(err) = written {
+            warn!("could not write {}: {}", cache_path, err);
+        }
+
+        Ok(origin)
+    }
+}
+
+fn read_cache(path: &str) -> Option<CachedImage> {
+    let bytes = fs::read(path).ok()?;
+    let (cached, _) = bincode::decode_from_slice(&bytes, bincode::config::standard()).ok()?;
+    Some(cached)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("lc3-cache-{}-{}", std::process::id(), name))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn write_image(path: &str, words: &[u16], modified: SystemTime) {
+        let bytes: Vec<u8> = std::iter::once(0x3000)
+            .chain(words.iter().copied())
+            .flat_map(u16::to_be_bytes)
+            .collect();
+        fs::write(path, bytes).unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    #[test]
+    fn the_second_load_comes_from_the_cache() {
+        let path = temp_path("second.obj");
+        let when = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        write_image(&path, &[0x1234, 0x5678], when);
+
+        let mut first = VM::new();
+        assert_eq!(first.read_image_cached(&path).unwrap(), 0x3000);
+        assert!(fs::metadata(format!("{}.cache", path)).is_ok());
+
+        /* same mtime, different contents: only the cache can supply the old words */
+        write_image(&path, &[0xFFFF, 0xFFFF], when);
+        let mut second = VM::new();
+        assert_eq!(second.read_image_cached(&path).unwrap(), 0x3000);
+        assert_eq!(second.memory(), first.memory());
+
+        fs::remove_file(format!("{}.cache", path)).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_changed_source_is_parsed_again() {
+        let path = temp_path("stale.obj");
+        write_image(
+            &path,
+            &[0x1234],
+            SystemTime::UNIX_EPOCH + Duration::from_secs(10),
+        );
+        VM::new().read_image_cached(&path).unwrap();
+
+        write_image(
+            &path,
+            &[0x4321],
+            SystemTime::UNIX_EPOCH + Duration::from_secs(20),
+        );
+        let mut vm = VM::new();
+        vm.read_image_cached(&path).unwrap();
+        assert_eq!(vm.peek(0x3000), 0x4321);
+
+        /* and the cache now holds the new image */
+        let mut again = VM::new();
+        again.read_image_cached(&path).unwrap();
+        assert_eq!(again.peek(0x3000), 0x4321);
+
+        fs::remove_file(format!("{}.cache", path)).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_corrupt_cache_falls_back_to_the_source() {
+        let path = temp_path("corrupt.obj");
+        write_image(
+            &path,
+            &[0x1234],
+            SystemTime::UNIX_EPOCH + Duration::from_secs(10),
+        );
+        fs::write(format!("{}.cache", path), b"not bincode").unwrap();
+
+        let mut vm = VM::new();
+        vm.read_image_cached(&path).unwrap();
+        assert_eq!(vm.peek(0x3000), 0x1234);
+
+        fs::remove_file(format!("{}.cache", path)).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_images_are_decompressed_before_caching() {
+        use std::io::Write;
+
+        let path = temp_path("packed.obj.gz");
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&[0x30, 0x00, 0x12, 0x34]).unwrap();
+        fs::write(&path, encoder.finish().unwrap()).unwrap();
+
+        for _ in 0..2 {
+            let mut vm = VM::new();
+            assert_eq!(vm.read_image_cached(&path).unwrap(), 0x3000);
+            assert_eq!(vm.peek(0x3000), 0x1234);
+        }
+
+        fs::remove_file(format!("{}.cache", path)).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+}
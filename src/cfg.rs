@@ -0,0 +1,249 @@
+//! Control-flow graphs recovered statically from memory.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::bitset::BitSet;
+use crate::disasm::disassemble;
+use crate::instruction::{decode, pc_relative, Instruction, TrapCode};
+use crate::vm::VM;
+
+/// How control gets from one block to the next.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Straight-line execution, including after a not-taken branch, a
+    /// subroutine call returning, or a TRAP.
+    Fallthrough,
+    /// A taken BR.
+    Branch,
+    /// A JSR to its subroutine.
+    Call,
+}
+
+/// A straight run of instructions entered only at `start`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: u16,
+    /// Address of the last instruction, inclusive.
+    pub end: u16,
+    pub successors: Vec<(u16, EdgeKind)>,
+}
+
+/// The blocks reachable from an entry point, keyed by start address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cfg {
+    pub blocks: BTreeMap<u16, BasicBlock>,
+}
+
+impl Cfg {
+    /// The graph in Graphviz DOT syntax, one node per block labelled with
+    /// its disassembly.
+    pub fn to_dot(&self, vm: &VM) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            let mut address = block.start;
+            loop {
+                let line = disassemble(address, vm.peek(address)).replace('"', "\\\"");
+                let _ = write!(label, "x{:04X}  {}\\l", address, line);
+                if address == block.end {
+                    break;
+                }
+                address = address.wrapping_add(1);
+            }
+            let _ = writeln!(dot, "    b{:04X} [label=\"{}\"];", block.start, label);
+
+            for (to, kind) in &block.successors {
+                let style = match kind {
+                    EdgeKind::Fallthrough => "",
+                    EdgeKind::Branch => " [label=\"branch\"]",
+                    EdgeKind::Call => " [label=\"call\", style=dashed]",
+                };
+                let _ = writeln!(dot, "    b{:04X} -> b{:04X}{};", block.start, to, style);
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/* what the instruction at an address does to control flow */
+enum Flow {
+    Next,
+    /* ends its block, with these successors */
+    End(Vec<(u16, EdgeKind)>),
+}
+
+impl VM {
+    /// Follows control flow from `start` through memory as it is now and
+    /// splits what it reaches into basic blocks. Blocks end at BR, JMP,
+    /// JSR/JSRR, TRAP, RTI and the reserved opcode. JMP and JSRR targets
+    /// come from registers and so are not followed: a RET block has no
+    /// successors.
+    pub fn build_cfg(&self, start: u16) -> Cfg {
+        /* first find every reachable instruction and where blocks begin */
+        let mut reached = BitSet::new();
+        let mut leaders = BTreeSet::from([start]);
+        let mut pending = vec![start];
+
+        while let Some(address) = pending.pop() {
+            if reached.contains(address) {
+                continue;
+            }
+            reached.insert(address);
+
+            match self.flow(address) {
+                Flow::Next => pending.push(address.wrapping_add(1)),
+                Flow::End(successors) => {
+                    for (to, _) in successors {
+                        leaders.insert(to);
+                        pending.push(to);
+                    }
+                }
+            }
+        }
+
+        /* then cut the reachable code at the leaders */
+        let mut cfg = Cfg::default();
+        for &leader in &leaders {
+            let mut end = leader;
+            let successors = loop {
+                match self.flow(end) {
+                    Flow::End(successors) => break successors,
+                    Flow::Next => {
+                        let next = end.wrapping_add(1);
+                        if leaders.contains(&next) {
+                            break vec![(next, EdgeKind::Fallthrough)];
+                        }
+                        end = next;
+                    }
+                }
+            };
+
+            cfg.blocks.insert(
+                leader,
+                BasicBlock {
+                    start: leader,
+                    end,
+                    successors,
+                },
+            );
+        }
+
+        cfg
+    }
+
+    fn flow(&self, address: u16) -> Flow {
+        let next = (address.wrapping_add(1), EdgeKind::Fallthrough);
+
+        let successors = match decode(self.peek(address)) {
+            /* never taken: just a NOP */
+            Instruction::Br { cond: 0, .. } => return Flow::Next,
+            Instruction::Br {
+                cond: 0b111,
+                offset,
+            } => {
+                vec![(pc_relative(address, offset), EdgeKind::Branch)]
+            }
+            Instruction::Br { offset, .. } => {
+                vec![(pc_relative(address, offset), EdgeKind::Branch), next]
+            }
+            Instruction::Jsr { offset } => {
+                vec![(pc_relative(address, offset), EdgeKind::Call), next]
+            }
+            Instruction::Jsrr { .. } => vec![next],
+            Instruction::Trap { vector } if vector == TrapCode::Halt as u8 => Vec::new(),
+            Instruction::Trap { .. } => vec![next],
+            Instruction::Jmp { .. } | Instruction::Res | Instruction::Rti => Vec::new(),
+            _ => return Flow::Next,
+        };
+
+        Flow::End(successors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::DEFAULT_ORIGIN;
+
+    fn loaded(words: &[u16]) -> VM {
+        let mut vm = VM::new();
+        vm.load_words(DEFAULT_ORIGIN, words);
+        vm
+    }
+
+    fn block(start: u16, end: u16, successors: &[(u16, EdgeKind)]) -> (u16, BasicBlock) {
+        (
+            start,
+            BasicBlock {
+                start,
+                end,
+                successors: successors.to_vec(),
+            },
+        )
+    }
+
+    #[test]
+    fn a_loop_splits_into_blocks_at_its_branches() {
+        let vm = loaded(&[
+            0x103F, // x3000 LOOP: ADD R0, R0, #-1
+            0x0402, // x3001 BRz DONE
+            0x1261, // x3002 ADD R1, R1, #1
+            0x0FFC, // x3003 BRnzp LOOP
+            0xF025, // x3004 DONE: HALT
+        ]);
+
+        assert_eq!(
+            vm.build_cfg(0x3000).blocks,
+            BTreeMap::from([
+                block(
+                    0x3000,
+                    0x3001,
+                    &[(0x3004, EdgeKind::Branch), (0x3002, EdgeKind::Fallthrough)]
+                ),
+                block(0x3002, 0x3003, &[(0x3000, EdgeKind::Branch)]),
+                block(0x3004, 0x3004, &[]),
+            ])
+        );
+    }
+
+    #[test]
+    fn calls_and_never_taken_branches() {
+        let vm = loaded(&[
+            0x4802, // x3000 JSR SUB
+            0x0000, // x3001 BR with no flags: a NOP
+            0xF025, // x3002 HALT
+            0x1021, // x3003 SUB: ADD R0, R0, #1
+            0xC1C0, // x3004 RET
+        ]);
+
+        assert_eq!(
+            vm.build_cfg(0x3000).blocks,
+            BTreeMap::from([
+                block(
+                    0x3000,
+                    0x3000,
+                    &[(0x3003, EdgeKind::Call), (0x3001, EdgeKind::Fallthrough)]
+                ),
+                block(0x3001, 0x3002, &[]),
+                block(0x3003, 0x3004, &[]),
+            ])
+        );
+    }
+
+    #[test]
+    fn dot_output_has_a_node_per_block_and_labelled_edges() {
+        let vm = loaded(&[0x0401, 0xF025, 0xF025]);
+        let dot = vm.build_cfg(0x3000).to_dot(&vm);
+
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.contains("    b3000 [label=\"x3000  BRz"));
+        assert!(dot.contains("    b3000 -> b3002 [label=\"branch\"];\n"));
+        assert!(dot.contains("    b3000 -> b3001;\n"));
+        assert!(dot.contains("    b3001 [label="));
+        assert!(dot.ends_with("}\n"));
+    }
+}
@@ -0,0 +1,550 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::disasm::disassemble;
+use crate::error::{ParseError, VmError};
+use crate::instruction::Register;
+use crate::vm::{StepResult, VM};
+
+/// Line-oriented command interpreter driving a VM. Commands taking an
+/// address accept a number (`x3000`, `0x3000`, `#12288`, `12288`) or a
+/// loaded symbol name.
+pub struct Debugger {
+    vm: VM,
+    symbols: HashMap<String, u16>,
+}
+
+/// A debugger command line, as returned by `parse_command`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DbgCmd {
+    Step(u16),
+    Next,
+    Back,
+    Continue,
+    Until(Location),
+    Finish,
+    Break(Location),
+    Delete(Location),
+    Watch(Location),
+    Unwatch(Location),
+    WatchReg(Register),
+    UnwatchReg(Register),
+    List,
+    /// Text to queue as key presses, followed by Enter.
+    Input(String),
+    Regs,
+    Backtrace,
+    /// Start address and word count.
+    Mem(Location, u16),
+    Save {
+        path: String,
+        start: Location,
+        count: u16,
+    },
+    Help,
+    Quit,
+}
+
+/// An address argument as typed, a number or a symbol name. It is
+/// resolved with `Debugger::resolve` when the command runs, since
+/// `parse_command` does not know the loaded symbol table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location(pub String);
+
+const HELP: &str = "\
+commands:
+  step [count]         execute count instructions (default 1)
+  next                 step, running a whole TRAP as one step
+  back                 undo the last instruction
+  continue             run until a breakpoint or HALT
+  until <addr>         run until the PC reaches addr
+  finish               run until the current subroutine returns
+  break <addr>         set a breakpoint
+  delete <addr>        remove a breakpoint
+  watch <addr|reg>     stop after a store to addr or a change to reg
+  unwatch <addr|reg>   remove a watchpoint
+  list                 show breakpoints and watchpoints
+  input <text>         queue text and Enter as key presses
+  regs                 show registers
+  backtrace            show the return addresses of active calls
+  mem <addr> [count]   show memory (default 1 word)
+  save <file> <addr> <count>
+                       write memory out as an object image
+  quit                 leave the debugger
+short forms: s step, n next, c continue, b break, d delete, u until, q quit
+";
+
+impl Debugger {
+    pub fn new(vm: VM) -> Self {
+        Self {
+            vm,
+            symbols: HashMap::new(),
+        }
+    }
+
+    pub fn vm(&self) -> &VM {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut VM {
+        &mut self.vm
+    }
+
+    pub fn into_vm(self) -> VM {
+        self.vm
+    }
+
+    /// Adds `map` to the known symbols, replacing names already present.
+    pub fn load_symbols(&mut self, map: HashMap<String, u16>) {
+        self.symbols.extend(map);
+    }
+
+    /// Turns a command argument into an address: symbols win over numbers.
+    pub fn resolve(&self, token: &str) -> Option<u16> {
+        self.symbols
+            .get(token)
+            .copied()
+            .or_else(|| parse_number(token))
+    }
+
+    /// Runs one command line, writing its output to `out`. Returns
+    /// `Ok(false)` once the user asked to quit.
+    pub fn execute(&mut self, line: &str, out: &mut dyn Write) -> io::Result<bool> {
+        let command = match parse_command(line) {
+            Ok(command) => command,
+            Err(ParseError::Empty) => return Ok(true),
+            Err(err) => {
+                writeln!(out, "{}", err)?;
+                return Ok(true);
+            }
+        };
+
+        match command {
+            DbgCmd::Step(count) => {
+                let result = self.vm.step_n(count as u64);
+                self.report(result, out)?;
+            }
+            DbgCmd::Next => {
+                let result = self.vm.step_over_trap();
+                self.report(result, out)?;
+            }
+            DbgCmd::Continue => {
+                let result = self.vm.run_until_break();
+                self.report(result, out)?;
+            }
+            DbgCmd::Until(target) => {
+                let Some(target) = self.locate(&target, out)? else {
+                    return Ok(true);
+                };
+                let result = self.vm.run_until_pc(target, u64::MAX);
+                self.report(result, out)?;
+            }
+            DbgCmd::Back => {
+                if self.vm.step_back() {
+                    writeln!(out, "pc x{:04X}", self.vm.pc())?;
+                } else {
+                    writeln!(out, "nothing to undo")?;
+                }
+            }
+            DbgCmd::Finish => {
+                let result = self.vm.step_out();
+                self.report(result, out)?;
+            }
+            DbgCmd::WatchReg(reg) => {
+                self.vm.add_reg_watch(reg);
+                writeln!(out, "watching {:?}", reg)?;
+            }
+            DbgCmd::UnwatchReg(reg) => {
+                if self.vm.remove_reg_watch(reg) {
+                    writeln!(out, "stopped watching {:?}", reg)?;
+                } else {
+                    writeln!(out, "{:?} is not watched", reg)?;
+                }
+            }
+            DbgCmd::Break(ref at) | DbgCmd::Watch(ref at) => {
+                let Some(address) = self.locate(at, out)? else {
+                    return Ok(true);
+                };
+                if matches!(command, DbgCmd::Break(_)) {
+                    self.vm.add_breakpoint(address);
+                    writeln!(out, "breakpoint at x{:04X}", address)?;
+                } else {
+                    self.vm.add_watchpoint(address);
+                    writeln!(out, "watchpoint at x{:04X}", address)?;
+                }
+            }
+            DbgCmd::Delete(ref at) | DbgCmd::Unwatch(ref at) => {
+                let Some(address) = self.locate(at, out)? else {
+                    return Ok(true);
+                };
+                let (kind, removed) = if matches!(command, DbgCmd::Delete(_)) {
+                    ("breakpoint", self.vm.remove_breakpoint(address))
+                } else {
+                    ("watchpoint", self.vm.remove_watchpoint(address))
+                };
+
+                if removed {
+                    writeln!(out, "removed {} at x{:04X}", kind, address)?;
+                } else {
+                    writeln!(out, "no {} at x{:04X}", kind, address)?;
+                }
+            }
+            DbgCmd::List => {
+                for address in self.vm.breakpoints() {
+                    writeln!(out, "breakpoint x{:04X}", address)?;
+                }
+                for address in self.vm.watchpoints() {
+                    writeln!(out, "watchpoint x{:04X}", address)?;
+                }
+            }
+            DbgCmd::Input(text) => {
+                self.vm.feed_input(text.as_bytes());
+                self.vm.push_key(b'\n');
+            }
+            DbgCmd::Regs => write!(out, "{}", self.vm.dump_registers())?,
+            DbgCmd::Backtrace => {
+                let frames = self.vm.call_stack();
+                if frames.is_empty() {
+                    writeln!(out, "no calls in progress")?;
+                }
+                for (depth, address) in frames.iter().rev().enumerate() {
+                    writeln!(out, "#{} returns to x{:04X}", depth, address)?;
+                }
+            }
+            DbgCmd::Mem(start, count) => {
+                let Some(start) = self.locate(&start, out)? else {
+                    return Ok(true);
+                };
+
+                for i in 0..count {
+                    let address = start.wrapping_add(i);
+                    writeln!(out, "x{:04X}: x{:04X}", address, self.vm.peek(address))?;
+                }
+            }
+            DbgCmd::Save { path, start, count } => {
+                let Some(start) = self.locate(&start, out)? else {
+                    return Ok(true);
+                };
+
+                match self.vm.write_image(&path, start, count) {
+                    Ok(()) => writeln!(out, "saved {} words to {}", count, path)?,
+                    Err(err) => writeln!(out, "error: {}", err)?,
+                }
+            }
+            DbgCmd::Help => write!(out, "{}", HELP)?,
+            DbgCmd::Quit => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Reads commands from stdin until `quit` or end of input.
+    pub fn repl(&mut self) -> io::Result<()> {
+        let stdin = io::stdin();
+        let mut stdout = io::stdout();
+        let mut line = String::new();
+
+        loop {
+            write!(stdout, "(lc3) ")?;
+            stdout.flush()?;
+
+            line.clear();
+            if stdin.lock().read_line(&mut line)? == 0 || !self.execute(&line, &mut stdout)? {
+                return Ok(());
+            }
+        }
+    }
+
+    fn locate(&self, location: &Location, out: &mut dyn Write) -> io::Result<Option<u16>> {
+        let address = self.resolve(&location.0);
+        if address.is_none() {
+            writeln!(out, "unknown symbol {:?}", location.0)?;
+        }
+        Ok(address)
+    }
+
+    fn report(&self, result: Result<StepResult, VmError>, out: &mut dyn Write) -> io::Result<()> {
+        let pc = self.vm.pc();
+
+        match result {
+            Ok(StepResult::Continue) => writeln!(out, "pc x{:04X}", pc),
+            Ok(StepResult::Halted) => writeln!(out, "halted at x{:04X}", pc),
+            Ok(StepResult::Breakpoint(address)) => {
+                writeln!(out, "breakpoint at x{:04X}", address)
+            }
+            Ok(StepResult::Watchpoint(address)) => {
+                writeln!(out, "watchpoint x{:04X} written, pc x{:04X}", address, pc)
+            }
+            Ok(StepResult::ReachedTarget) => writeln!(out, "stopped at x{:04X}", pc),
+            Ok(StepResult::NeedsInput) => {
+                writeln!(out, "waiting for input at x{:04X}; use `input <text>`", pc)
+            }
+            Ok(StepResult::RegChanged { reg, old, new }) => writeln!(
+                out,
+                "{:?} changed x{:04X} -> x{:04X}, pc x{:04X}",
+                reg, old, new, pc
+            ),
+            Ok(StepResult::Trapped { vector, output }) => writeln!(
+                out,
+                "{} printed {} bytes, pc x{:04X}",
+                disassemble(0, 0xF000 | vector as u16),
+                output,
+                pc
+            ),
+            Ok(StepResult::LimitReached) => writeln!(out, "cycle limit reached at x{:04X}", pc),
+            Err(err) => writeln!(out, "error: {}", err),
+        }
+    }
+}
+
+/// Parses an `lc3as`-style `.sym` file. Lines look like `//\tMAIN  3000`
+/// with a hex address; headers and anything else unparsable are skipped.
+pub fn parse_symbol_table(text: &str) -> HashMap<String, u16> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.trim_start_matches('/').split_whitespace();
+            let name = fields.next()?;
+            let address = u16::from_str_radix(fields.next()?, 16).ok()?;
+            fields.next().is_none().then(|| (name.to_string(), address))
+        })
+        .collect()
+}
+
+/// Parses one debugger line. The common commands have short forms: `s`
+/// step, `n` next, `c` continue, `b` break, `d` delete, `u` until, `q` quit.
+pub fn parse_command(line: &str) -> Result<DbgCmd, ParseError> {
+    let mut words = line.split_whitespace();
+    let command = words.next().ok_or(ParseError::Empty)?;
+    let args: Vec<&str> = words.collect();
+
+    let number =
+        |token: &&str| parse_number(token).ok_or_else(|| ParseError::BadNumber(token.to_string()));
+    let location = |index: usize| {
+        args.get(index)
+            .ok_or(ParseError::Missing("address"))
+            .and_then(|token| parse_location(token))
+    };
+    let register = || args.first().and_then(|token| parse_register(token));
+
+    let parsed = match command {
+        "step" | "s" => DbgCmd::Step(args.first().map_or(Ok(1), number)?),
+        "next" | "n" => DbgCmd::Next,
+        "back" => DbgCmd::Back,
+        "continue" | "c" => DbgCmd::Continue,
+        "until" | "u" => DbgCmd::Until(location(0)?),
+        "finish" => DbgCmd::Finish,
+        "break" | "b" => DbgCmd::Break(location(0)?),
+        "delete" | "d" => DbgCmd::Delete(location(0)?),
+        "watch" => match register() {
+            Some(reg) => DbgCmd::WatchReg(reg),
+            None => DbgCmd::Watch(location(0)?),
+        },
+        "unwatch" => match register() {
+            Some(reg) => DbgCmd::UnwatchReg(reg),
+            None => DbgCmd::Unwatch(location(0)?),
+        },
+        "list" => DbgCmd::List,
+        /* everything after the command, so spaces survive */
+        "input" => DbgCmd::Input(line.trim_start()[command.len()..].trim().to_string()),
+        "regs" => DbgCmd::Regs,
+        "backtrace" | "bt" => DbgCmd::Backtrace,
+        "mem" => DbgCmd::Mem(location(0)?, args.get(1).map_or(Ok(1), number)?),
+        "save" => DbgCmd::Save {
+            path: args
+                .first()
+                .ok_or(ParseError::Missing("file name"))?
+                .to_string(),
+            start: location(1)?,
+            count: number(args.get(2).ok_or(ParseError::Missing("count"))?)?,
+        },
+        "help" => DbgCmd::Help,
+        "quit" | "q" => DbgCmd::Quit,
+        _ => return Err(ParseError::UnknownCommand(command.to_string())),
+    };
+
+    Ok(parsed)
+}
+
+/* any token, except one that starts like a number but isn't one */
+fn parse_location(token: &str) -> Result<Location, ParseError> {
+    if token.starts_with(|c: char| c == '#' || c.is_ascii_digit()) && parse_number(token).is_none()
+    {
+        return Err(ParseError::BadNumber(token.to_string()));
+    }
+    Ok(Location(token.to_string()))
+}
+
+/* x3000 / 0x3000 are hex, #12288 / 12288 are decimal */
+fn parse_number(token: &str) -> Option<u16> {
+    if let Some(hex) = token
+        .strip_prefix("0x")
+        .or_else(|| token.strip_prefix('x'))
+        .or_else(|| token.strip_prefix('X'))
+    {
+        u16::from_str_radix(hex, 16).ok()
+    } else {
+        token.strip_prefix('#').unwrap_or(token).parse().ok()
+    }
+}
+
+/* R0..R7, PC or COND, any case */
+fn parse_register(token: &str) -> Option<Register> {
+    match token.to_ascii_uppercase().as_str() {
+        "R0" => Some(Register::R0),
+        "R1" => Some(Register::R1),
+        "R2" => Some(Register::R2),
+        "R3" => Some(Register::R3),
+        "R4" => Some(Register::R4),
+        "R5" => Some(Register::R5),
+        "R6" => Some(Register::R6),
+        "R7" => Some(Register::R7),
+        "PC" => Some(Register::Pc),
+        "COND" => Some(Register::Cond),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* runs `line` and returns what it printed */
+    fn run(debugger: &mut Debugger, line: &str) -> String {
+        let mut out = Vec::new();
+        debugger.execute(line, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn with_symbols(symbols: &[(&str, u16)]) -> Debugger {
+        let mut debugger = Debugger::new(VM::new());
+        debugger.load_symbols(
+            symbols
+                .iter()
+                .map(|&(name, address)| (name.to_string(), address))
+                .collect(),
+        );
+        debugger
+    }
+
+    #[test]
+    fn breakpoints_can_be_set_by_symbol() {
+        let mut debugger = with_symbols(&[("MAIN", 0x3010)]);
+
+        assert_eq!(run(&mut debugger, "break MAIN"), "breakpoint at x3010\n");
+        assert_eq!(debugger.vm().breakpoints(), [0x3010]);
+    }
+
+    #[test]
+    fn memory_dumps_accept_symbols() {
+        let mut debugger = with_symbols(&[("BUFFER", 0x4000)]);
+        debugger.vm_mut().load_words(0x4000, &[0x0041, 0x0042]);
+
+        assert_eq!(
+            run(&mut debugger, "mem BUFFER 2"),
+            "x4000: x0041\nx4001: x0042\n"
+        );
+    }
+
+    #[test]
+    fn unknown_symbols_are_reported() {
+        let mut debugger = with_symbols(&[]);
+
+        assert_eq!(
+            run(&mut debugger, "break LOOP"),
+            "unknown symbol \"LOOP\"\n"
+        );
+        assert!(debugger.vm().breakpoints().is_empty());
+    }
+
+    #[test]
+    fn symbol_tables_parse_name_and_hex_address() {
+        let text = "// Symbol table\n// Scope level 0:\n//\tSymbol Name       Page Address\n\
+                    //\t----------------  ------------\n//\tMAIN              3000\n//\tLOOP              3004\n";
+        let symbols = parse_symbol_table(text);
+
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols["MAIN"], 0x3000);
+        assert_eq!(symbols["LOOP"], 0x3004);
+    }
+
+    #[test]
+    fn short_forms_parse_like_the_full_commands() {
+        let at = |token: &str| Location(token.to_string());
+        let pairs = [
+            ("s", "step"),
+            ("s 5", "step 5"),
+            ("n", "next"),
+            ("c", "continue"),
+            ("b x3000", "break x3000"),
+            ("d LOOP", "delete LOOP"),
+            ("u 12288", "until 12288"),
+            ("bt", "backtrace"),
+            ("q", "quit"),
+        ];
+        for (short, long) in pairs {
+            assert_eq!(parse_command(short), parse_command(long), "{}", short);
+        }
+
+        assert_eq!(parse_command("s"), Ok(DbgCmd::Step(1)));
+        assert_eq!(parse_command("s #4"), Ok(DbgCmd::Step(4)));
+        assert_eq!(parse_command("b x3000"), Ok(DbgCmd::Break(at("x3000"))));
+        assert_eq!(parse_command("c"), Ok(DbgCmd::Continue));
+        assert_eq!(parse_command("q"), Ok(DbgCmd::Quit));
+        assert_eq!(
+            parse_command("watch r3"),
+            Ok(DbgCmd::WatchReg(Register::R3))
+        );
+    }
+
+    #[test]
+    fn numbers_parse_in_every_base() {
+        for token in ["x3000", "X3000", "0x3000", "#12288", "12288"] {
+            assert_eq!(parse_number(token), Some(0x3000), "{}", token);
+        }
+        assert_eq!(parse_number("x10000"), None);
+        assert_eq!(parse_number("65536"), None);
+        assert_eq!(parse_number("#-1"), None);
+    }
+
+    #[test]
+    fn breakpoints_accept_every_number_format() {
+        for token in ["x3000", "0x3000", "#12288", "12288"] {
+            let mut debugger = with_symbols(&[]);
+            assert_eq!(
+                run(&mut debugger, &format!("b {}", token)),
+                "breakpoint at x3000\n"
+            );
+        }
+    }
+
+    #[test]
+    fn bad_commands_and_arguments_are_explained() {
+        assert_eq!(parse_command("   "), Err(ParseError::Empty));
+        assert_eq!(
+            parse_command("jump"),
+            Err(ParseError::UnknownCommand("jump".to_string()))
+        );
+        assert_eq!(parse_command("break"), Err(ParseError::Missing("address")));
+        assert_eq!(
+            parse_command("step ten"),
+            Err(ParseError::BadNumber("ten".to_string()))
+        );
+        assert_eq!(
+            parse_command("break #12x"),
+            Err(ParseError::BadNumber("#12x".to_string()))
+        );
+        assert_eq!(
+            parse_command("save out.obj x3000"),
+            Err(ParseError::Missing("count"))
+        );
+    }
+
+    #[test]
+    fn symbols_win_over_numbers_that_look_the_same() {
+        let mut debugger = with_symbols(&[("xBAD", 0x3010)]);
+
+        assert_eq!(debugger.resolve("xBAD"), Some(0x3010));
+        assert_eq!(run(&mut debugger, "break xBAD"), "breakpoint at x3010\n");
+        assert_eq!(debugger.vm().breakpoints(), [0x3010]);
+    }
+}
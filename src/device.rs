@@ -0,0 +1,11 @@
+/// A memory-mapped peripheral registered with `VM::map_device`. Loads and
+/// stores by the program to its address go here instead of to memory.
+pub trait MmioDevice {
+    /// A load from the device's address.
+    fn read(&mut self) -> u16;
+
+    /// A store to the device's address. Read-only devices ignore it.
+    fn write(&mut self, value: u16) {
+        let _ = value;
+    }
+}
@@ -0,0 +1,90 @@
+use crate::instruction::{Register, GENERAL_REGISTERS};
+use crate::vm::VM;
+
+/* registers compared by `diff` after the general ones, in report order */
+const SPECIAL_REGISTERS: [Register; 2] = [Register::Pc, Register::Cond];
+
+/* words compared at once before looking at individual cells */
+const SPAN: usize = 64;
+
+/// Where two machines differ, as `(what, self's value, other's value)`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    pub registers: Vec<(Register, u16, u16)>,
+    /// Differing memory cells in ascending address order.
+    pub memory: Vec<(u16, u16, u16)>,
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.registers.is_empty() && self.memory.is_empty()
+    }
+}
+
+impl VM {
+    /// Compares registers and memory with `other`. If the memory sizes
+    /// differ only the addresses both have are compared.
+    pub fn diff(&self, other: &VM) -> StateDiff {
+        let registers = GENERAL_REGISTERS
+            .iter()
+            .chain(&SPECIAL_REGISTERS)
+            .map(|&reg| (reg, self.get_register(reg), other.get_register(reg)))
+            .filter(|(_, ours, theirs)| ours != theirs)
+            .collect();
+
+        let mut memory = Vec::new();
+        let spans = self.memory().chunks(SPAN).zip(other.memory().chunks(SPAN));
+        for (index, (ours, theirs)) in spans.enumerate() {
+            if ours == theirs {
+                continue;
+            }
+
+            for (offset, (&a, &b)) in ours.iter().zip(theirs).enumerate() {
+                if a != b {
+                    memory.push(((index * SPAN + offset) as u16, a, b));
+                }
+            }
+        }
+
+        StateDiff { registers, memory }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_exactly_the_differing_register_and_word() {
+        let mut ours = VM::new();
+        let mut theirs = VM::new();
+        assert!(ours.diff(&theirs).is_empty());
+
+        ours.set_register(Register::R3, 7);
+        theirs.set_register(Register::R3, 9);
+        ours.load_words(0x4000, &[0x1234]);
+
+        assert_eq!(
+            ours.diff(&theirs),
+            StateDiff {
+                registers: vec![(Register::R3, 7, 9)],
+                memory: vec![(0x4000, 0x1234, 0)],
+            }
+        );
+    }
+
+    #[test]
+    fn finds_words_on_either_side_of_a_span_boundary() {
+        let mut ours = VM::new();
+        let theirs = VM::new();
+        ours.load_words(SPAN as u16 - 1, &[1, 2]);
+        ours.load_words(0xFDFF, &[3]);
+
+        let diff = ours.diff(&theirs);
+        assert!(diff.registers.is_empty());
+        assert_eq!(
+            diff.memory,
+            vec![(SPAN as u16 - 1, 1, 0), (SPAN as u16, 2, 0), (0xFDFF, 3, 0)]
+        );
+    }
+}
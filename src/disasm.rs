@@ -0,0 +1,162 @@
+//! Turning words back into LC-3 assembly for listings and the debugger.
+
+use crate::instruction::{
+    decode, pc_relative, to_signed, Instruction, Operand, Register, TrapCode,
+};
+use crate::vm::VM;
+
+/// The instruction `word` at `address`, in assembler syntax. PC-relative
+/// targets are shown as absolute addresses, e.g. `BRnz x3001`.
+pub fn disassemble(address: u16, word: u16) -> String {
+    let target = |offset: u16| format!("x{:04X}", pc_relative(address, offset));
+
+    match decode(word) {
+        Instruction::Br { .. } if is_nop(word) => String::from("NOP"),
+        Instruction::Br { cond, offset } => {
+            let flags: String = [(4, 'n'), (2, 'z'), (1, 'p')]
+                .iter()
+                .filter(|(bit, _)| cond & bit != 0)
+                .map(|&(_, flag)| flag)
+                .collect();
+            format!("BR{} {}", flags, target(offset))
+        }
+        Instruction::Add { dr, sr1, operand } => {
+            format!("ADD {}, {}, {}", reg(dr), reg(sr1), operand_text(operand))
+        }
+        Instruction::And { dr, sr1, operand } => {
+            format!("AND {}, {}, {}", reg(dr), reg(sr1), operand_text(operand))
+        }
+        Instruction::Not { dr, sr } => format!("NOT {}, {}", reg(dr), reg(sr)),
+        Instruction::Ld { dr, offset } => format!("LD {}, {}", reg(dr), target(offset)),
+        Instruction::Ldi { dr, offset } => format!("LDI {}, {}", reg(dr), target(offset)),
+        Instruction::Lea { dr, offset } => format!("LEA {}, {}", reg(dr), target(offset)),
+        Instruction::St { sr, offset } => format!("ST {}, {}", reg(sr), target(offset)),
+        Instruction::Sti { sr, offset } => format!("STI {}, {}", reg(sr), target(offset)),
+        Instruction::Ldr { dr, base, offset } => {
+            format!("LDR {}, {}, #{}", reg(dr), reg(base), to_signed(offset))
+        }
+        Instruction::Str { sr, base, offset } => {
+            format!("STR {}, {}, #{}", reg(sr), reg(base), to_signed(offset))
+        }
+        Instruction::Jmp { base: Register::R7 } => String::from("RET"),
+        Instruction::Jmp { base } => format!("JMP {}", reg(base)),
+        Instruction::Jsr { offset } => format!("JSR {}", target(offset)),
+        Instruction::Jsrr { base } => format!("JSRR {}", reg(base)),
+        Instruction::Rti => String::from("RTI"),
+        Instruction::Res => String::from("RES"),
+        Instruction::Trap { vector } => match TrapCode::try_from(vector as u16) {
+            Ok(TrapCode::Getc) => String::from("GETC"),
+            Ok(TrapCode::Out) => String::from("OUT"),
+            Ok(TrapCode::Puts) => String::from("PUTS"),
+            Ok(TrapCode::In) => String::from("IN"),
+            Ok(TrapCode::Putsp) => String::from("PUTSP"),
+            Ok(TrapCode::Halt) => String::from("HALT"),
+            Err(()) => format!("TRAP x{:02X}", vector),
+        },
+    }
+}
+
+/// Whether `word` is a BR with no condition bits set, which never branches
+/// and so is the LC-3 NOP. Its offset is ignored, so x0000 through x01FF
+/// all count.
+pub fn is_nop(word: u16) -> bool {
+    matches!(decode(word), Instruction::Br { cond: 0, .. })
+}
+
+/// Guesses whether `word` is data rather than code. Words that would be
+/// a never-taken branch (which covers every ASCII character and zero),
+/// the reserved opcode and RTI are treated as data.
+pub fn looks_like_data(word: u16) -> bool {
+    is_nop(word) || matches!(decode(word), Instruction::Res | Instruction::Rti)
+}
+
+impl VM {
+    /// An address/word/mnemonic listing of `len` words from `start`, one
+    /// per line, read without device side effects. Words that look like
+    /// data are shown as `.FILL`, with the character if printable.
+    pub fn listing(&self, start: u16, len: usize) -> String {
+        let mut text = String::new();
+
+        for i in 0..len {
+            let address = start.wrapping_add(i as u16);
+            let word = self.peek(address);
+
+            let body = if !looks_like_data(word) {
+                disassemble(address, word)
+            } else if (0x20..0x7F).contains(&word) {
+                format!(".FILL x{:04X} ; {:?}", word, word as u8 as char)
+            } else {
+                format!(".FILL x{:04X}", word)
+            };
+            text.push_str(&format!("x{:04X}  {:04X}  {}\n", address, word, body));
+        }
+
+        text
+    }
+}
+
+fn reg(reg: Register) -> String {
+    format!("{:?}", reg)
+}
+
+fn operand_text(operand: Operand) -> String {
+    match operand {
+        Operand::Reg(sr2) => reg(sr2),
+        Operand::Imm(imm5) => format!("#{}", to_signed(imm5)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::*;
+    use Register::*;
+
+    #[test]
+    fn negative_immediates_print_as_signed_decimal() {
+        assert_eq!(
+            disassemble(0x3000, encode_add_imm(R0, R0, -1)),
+            "ADD R0, R0, #-1"
+        );
+        assert_eq!(
+            disassemble(0x3000, encode_ldr(R1, R6, -32)),
+            "LDR R1, R6, #-32"
+        );
+        assert_eq!(
+            disassemble(0x3000, encode_str(R1, R6, 31)),
+            "STR R1, R6, #31"
+        );
+    }
+
+    #[test]
+    fn branches_with_no_flags_are_nops() {
+        assert!(is_nop(0x0000));
+        assert!(is_nop(0x01FF));
+        assert_eq!(disassemble(0x3000, 0x0000), "NOP");
+        assert_eq!(disassemble(0x3000, 0x0005), "NOP");
+    }
+
+    #[test]
+    fn conditional_branches_are_not_nops() {
+        for word in [
+            encode_br(false, true, false, 0),
+            encode_br(true, true, true, -1),
+        ] {
+            assert!(!is_nop(word), "x{:04X}", word);
+            assert!(disassemble(0x3000, word).starts_with("BR"));
+        }
+        assert_eq!(disassemble(0x3000, 0x0E00), "BRnzp x3001");
+        assert!(!is_nop(0x1020), "ADD R0, R0, #0 changes the flags");
+    }
+
+    #[test]
+    fn jsr_and_jsrr_disassemble_differently() {
+        assert_eq!(disassemble(0x3000, encode_jsr(4)), "JSR x3005");
+        assert_eq!(disassemble(0x3000, encode_jsr(-1)), "JSR x3000");
+        assert_eq!(disassemble(0x3000, 0x4C00), "JSR x2C01");
+        assert_eq!(disassemble(0x3000, encode_jsrr(R2)), "JSRR R2");
+        /* bit 11 alone decides: JSRR's base field is not an offset */
+        assert_eq!(disassemble(0x3000, 0x41C0), "JSRR R7");
+        assert_eq!(disassemble(0x3000, 0x49C0), "JSR x31C1");
+    }
+}
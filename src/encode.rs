@@ -0,0 +1,207 @@
+//! One helper per instruction form, so programs can be written readably
+//! instead of as hand-computed hex. Offsets are relative to the
+//! incremented PC, as in assembly.
+
+use crate::instruction::{Instruction, Operand, Register};
+
+/// BR with the given n/z/p flags.
+pub fn encode_br(n: bool, z: bool, p: bool, offset: i16) -> u16 {
+    let cond = (n as u16) << 2 | (z as u16) << 1 | p as u16;
+    Instruction::Br {
+        cond,
+        offset: offset as u16,
+    }
+    .encode()
+}
+
+pub fn encode_add(dr: Register, sr1: Register, sr2: Register) -> u16 {
+    Instruction::Add {
+        dr,
+        sr1,
+        operand: Operand::Reg(sr2),
+    }
+    .encode()
+}
+
+pub fn encode_add_imm(dr: Register, sr1: Register, imm5: i16) -> u16 {
+    Instruction::Add {
+        dr,
+        sr1,
+        operand: Operand::Imm(imm5 as u16),
+    }
+    .encode()
+}
+
+pub fn encode_and(dr: Register, sr1: Register, sr2: Register) -> u16 {
+    Instruction::And {
+        dr,
+        sr1,
+        operand: Operand::Reg(sr2),
+    }
+    .encode()
+}
+
+pub fn encode_and_imm(dr: Register, sr1: Register, imm5: i16) -> u16 {
+    Instruction::And {
+        dr,
+        sr1,
+        operand: Operand::Imm(imm5 as u16),
+    }
+    .encode()
+}
+
+pub fn encode_not(dr: Register, sr: Register) -> u16 {
+    Instruction::Not { dr, sr }.encode()
+}
+
+pub fn encode_ld(dr: Register, offset: i16) -> u16 {
+    Instruction::Ld {
+        dr,
+        offset: offset as u16,
+    }
+    .encode()
+}
+
+pub fn encode_ldi(dr: Register, offset: i16) -> u16 {
+    Instruction::Ldi {
+        dr,
+        offset: offset as u16,
+    }
+    .encode()
+}
+
+pub fn encode_ldr(dr: Register, base: Register, offset: i16) -> u16 {
+    Instruction::Ldr {
+        dr,
+        base,
+        offset: offset as u16,
+    }
+    .encode()
+}
+
+pub fn encode_lea(dr: Register, offset: i16) -> u16 {
+    Instruction::Lea {
+        dr,
+        offset: offset as u16,
+    }
+    .encode()
+}
+
+pub fn encode_st(sr: Register, offset: i16) -> u16 {
+    Instruction::St {
+        sr,
+        offset: offset as u16,
+    }
+    .encode()
+}
+
+pub fn encode_sti(sr: Register, offset: i16) -> u16 {
+    Instruction::Sti {
+        sr,
+        offset: offset as u16,
+    }
+    .encode()
+}
+
+pub fn encode_str(sr: Register, base: Register, offset: i16) -> u16 {
+    Instruction::Str {
+        sr,
+        base,
+        offset: offset as u16,
+    }
+    .encode()
+}
+
+pub fn encode_jmp(base: Register) -> u16 {
+    Instruction::Jmp { base }.encode()
+}
+
+/// `RET` is `JMP R7`.
+pub fn encode_ret() -> u16 {
+    encode_jmp(Register::R7)
+}
+
+pub fn encode_jsr(offset: i16) -> u16 {
+    Instruction::Jsr {
+        offset: offset as u16,
+    }
+    .encode()
+}
+
+pub fn encode_jsrr(base: Register) -> u16 {
+    Instruction::Jsrr { base }.encode()
+}
+
+pub fn encode_rti() -> u16 {
+    Instruction::Rti.encode()
+}
+
+pub fn encode_trap(vector: u8) -> u16 {
+    Instruction::Trap { vector }.encode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::disassemble;
+    use crate::instruction::decode;
+    use Register::*;
+
+    #[test]
+    fn encoders_disassemble_back_to_their_assembly() {
+        let cases = [
+            (encode_add(R1, R2, R3), "ADD R1, R2, R3"),
+            (encode_add_imm(R0, R0, -16), "ADD R0, R0, #-16"),
+            (encode_and(R4, R5, R6), "AND R4, R5, R6"),
+            (encode_and_imm(R7, R7, 15), "AND R7, R7, #15"),
+            (encode_not(R2, R3), "NOT R2, R3"),
+            (encode_br(true, false, true, -1), "BRnp x3000"),
+            (encode_ld(R1, 255), "LD R1, x3100"),
+            (encode_ldi(R2, -256), "LDI R2, x2F01"),
+            (encode_ldr(R3, R6, -32), "LDR R3, R6, #-32"),
+            (encode_lea(R0, 2), "LEA R0, x3003"),
+            (encode_st(R4, 0), "ST R4, x3001"),
+            (encode_sti(R5, 1), "STI R5, x3002"),
+            (encode_str(R6, R5, 31), "STR R6, R5, #31"),
+            (encode_jmp(R3), "JMP R3"),
+            (encode_ret(), "RET"),
+            (encode_jsr(-1024), "JSR x2C01"),
+            (encode_jsrr(R4), "JSRR R4"),
+            (encode_rti(), "RTI"),
+            (encode_trap(0x25), "HALT"),
+            (encode_trap(0x40), "TRAP x40"),
+        ];
+
+        for (word, text) in cases {
+            assert_eq!(disassemble(0x3000, word), text, "x{:04X}", word);
+        }
+    }
+
+    #[test]
+    fn encoders_decode_to_the_fields_they_were_given() {
+        assert_eq!(
+            decode(encode_ldr(R1, R2, -1)),
+            Instruction::Ldr {
+                dr: R1,
+                base: R2,
+                offset: 0xFFFF,
+            }
+        );
+        assert_eq!(
+            decode(encode_br(false, true, false, 5)),
+            Instruction::Br {
+                cond: 0b010,
+                offset: 5,
+            }
+        );
+        assert_eq!(decode(encode_jsr(1023)), Instruction::Jsr { offset: 1023 });
+    }
+
+    #[test]
+    fn known_encodings() {
+        assert_eq!(encode_add_imm(R0, R0, 1), 0x1021);
+        assert_eq!(encode_ret(), 0xC1C0);
+        assert_eq!(encode_trap(0x25), 0xF025);
+        assert_eq!(encode_br(true, true, true, -1), 0x0FFF);
+    }
+}
@@ -0,0 +1,206 @@
+use std::{fmt, io};
+
+/// Reasons execution can stop other than a clean HALT.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmError {
+    /// `instr` fetched from `pc` is not something the VM can execute.
+    IllegalOpcode { pc: u16, instr: u16 },
+    /// `instr` at `pc` uses the reserved opcode (1101): the program is broken.
+    Reserved { pc: u16, instr: u16 },
+    /// The program executed RTI at `pc`, a real instruction the VM does not
+    /// implement.
+    UnimplementedRti { pc: u16 },
+    /// TRAP at `pc` named a vector with no built-in routine.
+    UnknownTrap { pc: u16, vector: u8 },
+    /// The PC reached this address among the device registers.
+    ExecuteDeviceMemory(u16),
+    /// Execution ran through enough data-looking words, starting at `pc`,
+    /// to hit `Options::data_execution_limit`.
+    SuspectedDataExecution { pc: u16 },
+    /// The branch, jump or call at `pc` went to x0000.
+    JumpToZero { pc: u16 },
+    /// Execution ran past xFFFF and wrapped around to x0000.
+    PcWrapped,
+    /// A store targeted the protected system region at this address.
+    ProtectionFault(u16),
+    /// A store to this address went past `Options::write_quota`.
+    WriteQuotaExceeded(u16),
+    /// LDR/STR at `pc` used R6 to reach `address`, outside the configured
+    /// stack region.
+    StackOverflow { pc: u16, address: u16 },
+    /// The program did not halt within this many instructions.
+    CycleLimitExceeded(u64),
+    /// GETC or IN needed a key but the input source is exhausted.
+    InputClosed,
+    /// Writing program output failed.
+    OutputFailed(io::ErrorKind),
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::IllegalOpcode { pc, instr } => {
+                write!(f, "illegal opcode x{:04X} at x{:04X}", instr, pc)
+            }
+            VmError::Reserved { pc, instr } => {
+                write!(f, "reserved opcode in x{:04X} at x{:04X}", instr, pc)
+            }
+            VmError::UnimplementedRti { pc } => {
+                write!(f, "RTI at x{:04X} is not supported by this VM", pc)
+            }
+            VmError::UnknownTrap { pc, vector } => {
+                write!(f, "unknown trap x{:02X} at x{:04X}", vector, pc)
+            }
+            VmError::ExecuteDeviceMemory(address) => {
+                write!(f, "jumped into device memory at x{:04X}", address)
+            }
+            VmError::SuspectedDataExecution { pc } => write!(
+                f,
+                "executing what looks like data from x{:04X}; missing HALT?",
+                pc
+            ),
+            VmError::JumpToZero { pc } => write!(f, "jump to x0000 from x{:04X}", pc),
+            VmError::PcWrapped => write!(f, "execution ran off the end of memory"),
+            VmError::ProtectionFault(address) => {
+                write!(f, "write to protected system memory at x{:04X}", address)
+            }
+            VmError::WriteQuotaExceeded(address) => {
+                write!(f, "memory write quota used up at x{:04X}", address)
+            }
+            VmError::StackOverflow { pc, address } => write!(
+                f,
+                "stack access at x{:04X} outside the stack region from x{:04X}",
+                address, pc
+            ),
+            VmError::CycleLimitExceeded(limit) => {
+                write!(f, "no HALT within {} instructions", limit)
+            }
+            VmError::InputClosed => write!(f, "input closed while waiting for a key"),
+            VmError::OutputFailed(kind) => write!(f, "writing output failed: {}", kind),
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+/// Why an object image could not be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The image file does not exist.
+    NotFound,
+    /// The path names a directory rather than a file.
+    IsDirectory,
+    /// The file exists but may not be read.
+    PermissionDenied,
+    /// The image could not be read for any other I/O reason.
+    Io(io::Error),
+    /// The bytes were read but are not a valid object image.
+    BadFormat(&'static str),
+    /// A text image could not be parsed at this 1-based line.
+    Parse { line: usize, message: String },
+    /// `len` payload words starting at `origin` run past the end of memory,
+    /// or start at xFFFF, where no payload may load.
+    OutOfRange { origin: u16, len: usize },
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::NotFound => LoadError::NotFound,
+            io::ErrorKind::IsADirectory => LoadError::IsDirectory,
+            io::ErrorKind::PermissionDenied => LoadError::PermissionDenied,
+            _ => LoadError::Io(err),
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::NotFound => write!(f, "file not found"),
+            LoadError::IsDirectory => write!(f, "is a directory"),
+            LoadError::PermissionDenied => write!(f, "permission denied"),
+            LoadError::Io(err) => write!(f, "{}", err),
+            LoadError::BadFormat(reason) => write!(f, "bad image format: {}", reason),
+            LoadError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            LoadError::OutOfRange { origin, len } => write!(
+                f,
+                "{} words at x{:04X} run past the end of memory",
+                len, origin
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Why `asm_one` could not encode a line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmError {
+    /// The first word is not an instruction or trap alias.
+    UnknownMnemonic(String),
+    /// The instruction takes `expected` operands but the line has `found`.
+    OperandCount { expected: usize, found: usize },
+    /// Not a register or number where one was expected.
+    BadOperand(String),
+    /// The number doesn't fit the instruction's `bits`-wide field.
+    OutOfRange { operand: String, bits: u8 },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsmError::UnknownMnemonic(mnemonic) => write!(f, "unknown mnemonic {:?}", mnemonic),
+            AsmError::OperandCount { expected, found } => {
+                write!(f, "expected {} operands, found {}", expected, found)
+            }
+            AsmError::BadOperand(operand) => write!(f, "bad operand {:?}", operand),
+            AsmError::OutOfRange { operand, bits } => {
+                write!(f, "{} does not fit in {} bits", operand, bits)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Why a debugger command line could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The line was blank.
+    Empty,
+    /// The first word is not a command or abbreviation.
+    UnknownCommand(String),
+    /// The command needs this argument, e.g. "address".
+    Missing(&'static str),
+    /// A count was not a number in any accepted base.
+    BadNumber(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "empty command"),
+            ParseError::UnknownCommand(command) => {
+                write!(f, "unknown command {:?}; try help", command)
+            }
+            ParseError::Missing(what) => write!(f, "missing {}", what),
+            ParseError::BadNumber(token) => {
+                write!(
+                    f,
+                    "bad number {:?}; use x3000, 0x3000, #12288 or 12288",
+                    token
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
@@ -0,0 +1,193 @@
+use std::io::{self, BufRead};
+
+#[cfg(feature = "terminal")]
+use std::collections::VecDeque;
+#[cfg(feature = "terminal")]
+use std::io::IsTerminal;
+#[cfg(feature = "terminal")]
+use std::time::Duration;
+
+#[cfg(feature = "terminal")]
+use crossterm::event::{self, Event, KeyCode, KeyEvent};
+#[cfg(feature = "terminal")]
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+
+/// Where GETC and IN get their characters from.
+pub trait Input {
+    /// Blocks until the next byte is available; `None` once input is exhausted.
+    fn getchar(&mut self) -> Option<u8>;
+
+    /// Returns a byte only if one is ready right now, never waiting. Used
+    /// for KBSR polling. The default has nothing ready, ever.
+    fn try_getchar(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+/// The keyboard in crossterm raw mode, so keys arrive unbuffered and
+/// unechoed. Raw mode is only on while a key is being read or polled for,
+/// so the terminal is back in cooked mode whenever the program is not
+/// reading, e.g. at a debugger prompt. If stdin is not a terminal, or raw
+/// mode can't be enabled (`cargo test`, some IDE consoles), it switches
+/// for good to reading line-buffered stdin. Without the `terminal`
+/// feature it only ever reads stdin that way.
+#[derive(Default)]
+pub struct TerminalInput {
+    /* reading stdin directly; its lock is only held for each byte, so the
+    debugger's prompt can read stdin between keys */
+    fallback: bool,
+    /* keys that arrived in the same burst as an earlier one, e.g. a paste.
+    Bracketed paste is never enabled, so a paste comes as key events and
+    never as Event::Paste */
+    #[cfg(feature = "terminal")]
+    queued: VecDeque<u8>,
+}
+
+impl TerminalInput {
+    /* a key queued from an earlier burst, else `read` run in raw mode, or
+    None once the fallback is in use. A piped stdin must not use raw mode:
+    crossterm would read /dev/tty instead */
+    #[cfg(feature = "terminal")]
+    fn raw(&mut self, read: fn() -> Option<u8>) -> Option<Option<u8>> {
+        if let Some(ch) = self.queued.pop_front() {
+            return Some(Some(ch));
+        }
+        if self.fallback || !io::stdin().is_terminal() || enable_raw_mode().is_err() {
+            return None;
+        }
+
+        let ch = read();
+        /* drain the rest of the burst now: once raw mode is off the
+        terminal would line-buffer and echo it */
+        while let Some(next) = poll_key_event() {
+            self.queued.push_back(next);
+        }
+        let _ = disable_raw_mode();
+        Some(ch)
+    }
+
+    /* one byte of line-buffered stdin, locking it only for that byte */
+    fn fallback(&mut self) -> Option<u8> {
+        self.fallback = true;
+        LineInput::new(io::stdin().lock()).getchar()
+    }
+}
+
+impl Input for TerminalInput {
+    fn getchar(&mut self) -> Option<u8> {
+        #[cfg(feature = "terminal")]
+        if let Some(ch) = self.raw(read_key_event) {
+            return ch;
+        }
+
+        self.fallback()
+    }
+
+    /* a pipe or file is never waiting on a person, so reading counts as ready */
+    fn try_getchar(&mut self) -> Option<u8> {
+        #[cfg(feature = "terminal")]
+        if let Some(ch) = self.raw(poll_key_event) {
+            return ch;
+        }
+
+        self.fallback()
+    }
+}
+
+/// Bytes one at a time from any buffered reader: stdin, a file, or a
+/// byte slice in tests.
+pub struct LineInput<R> {
+    reader: R,
+}
+
+impl<R: BufRead> LineInput<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+}
+
+impl<R: BufRead> Input for LineInput<R> {
+    fn getchar(&mut self) -> Option<u8> {
+        let mut byte = [0];
+        self.reader.read_exact(&mut byte).ok()?;
+        Some(byte[0])
+    }
+
+    /* a reader is never waiting on a person, so reading counts as ready */
+    fn try_getchar(&mut self) -> Option<u8> {
+        self.getchar()
+    }
+}
+
+/* next ASCII character key; non-character keys are skipped */
+#[cfg(feature = "terminal")]
+fn read_key_event() -> Option<u8> {
+    loop {
+        if let Some(ch) = key_char(event::read().ok()?) {
+            return Some(ch);
+        }
+    }
+}
+
+/* like read_key_event, but gives up as soon as no event is queued */
+#[cfg(feature = "terminal")]
+fn poll_key_event() -> Option<u8> {
+    while event::poll(Duration::ZERO).ok()? {
+        if let Some(ch) = key_char(event::read().ok()?) {
+            return Some(ch);
+        }
+    }
+
+    None
+}
+
+/* the ASCII character typed by a key event, if it is one */
+#[cfg(feature = "terminal")]
+fn key_char(event: Event) -> Option<u8> {
+    match event {
+        Event::Key(KeyEvent {
+            code: KeyCode::Char(c),
+            ..
+        }) if c.is_ascii() => Some(c as u8),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_input_yields_bytes_in_order_then_none() {
+        let mut input = LineInput::new(&b"ab\n"[..]);
+
+        assert_eq!(input.getchar(), Some(b'a'));
+        assert_eq!(input.getchar(), Some(b'b'));
+        assert_eq!(input.getchar(), Some(b'\n'));
+        assert_eq!(input.getchar(), None);
+    }
+
+    #[cfg(feature = "terminal")]
+    #[test]
+    fn a_burst_of_keys_arrives_one_per_read_in_order() {
+        use crossterm::event::KeyModifiers;
+
+        let key = |code| Event::Key(KeyEvent::new(code, KeyModifiers::NONE));
+        let burst = [
+            key(KeyCode::Char('a')),
+            key(KeyCode::Left),
+            key(KeyCode::Char('b')),
+            key(KeyCode::Char('\u{e9}')),
+            key(KeyCode::Char('c')),
+        ];
+        let mut input = TerminalInput {
+            fallback: false,
+            queued: burst.into_iter().filter_map(key_char).collect(),
+        };
+
+        assert_eq!(input.try_getchar(), Some(b'a'));
+        assert_eq!(input.getchar(), Some(b'b'));
+        assert_eq!(input.getchar(), Some(b'c'));
+        assert!(input.queued.is_empty());
+    }
+}
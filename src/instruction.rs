@@ -0,0 +1,559 @@
+#[repr(u16)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Register {
+    R0,
+    R1,
+    R2,
+    R3,
+    R4,
+    R5,
+    R6,
+    R7,
+    Pc,
+    Cond,
+}
+
+/// The eight registers addressable by a 3 bit instruction field, indexed
+/// by that field.
+pub const GENERAL_REGISTERS: [Register; 8] = [
+    Register::R0,
+    Register::R1,
+    Register::R2,
+    Register::R3,
+    Register::R4,
+    Register::R5,
+    Register::R6,
+    Register::R7,
+];
+
+impl TryFrom<u16> for Register {
+    type Error = ();
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Register::R0),
+            1 => Ok(Register::R1),
+            2 => Ok(Register::R2),
+            3 => Ok(Register::R3),
+            4 => Ok(Register::R4),
+            5 => Ok(Register::R5),
+            6 => Ok(Register::R6),
+            7 => Ok(Register::R7),
+            8 => Ok(Register::Pc),
+            9 => Ok(Register::Cond),
+            _ => Err(()),
+        }
+    }
+}
+
+#[repr(u16)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OpCode {
+    Br,   /* Branch */
+    Add,  /* add */
+    Ld,   /* load */
+    St,   /* store */
+    Jsr,  /* jump reguster */
+    And,  /* bitwise and */
+    Ldr,  /* load register */
+    Str,  /* store register */
+    Rti,  /* unused */
+    Not,  /* bitwise not */
+    Ldi,  /* load indirect */
+    Sti,  /* store indirect */
+    Jmp,  /* jump */
+    Res,  /* reserved (unused) */
+    Lea,  /* load effective address */
+    Trap, /* execute trap */
+}
+
+impl TryFrom<u16> for OpCode {
+    type Error = ();
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        if value < 16 {
+            Ok(Self::from_instruction(value << 12))
+        } else {
+            Err(())
+        }
+    }
+}
+
+impl OpCode {
+    /// The opcode in the top four bits of an instruction word. Every word
+    /// has one, so unlike `try_from` this cannot fail.
+    pub fn from_instruction(word: u16) -> OpCode {
+        decode(word).opcode()
+    }
+}
+
+#[repr(u16)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConditionFlag {
+    Pos = 1 << 0, /* P */
+    Zro = 1 << 1, /* Z */
+    Neg = 1 << 2, /* N */
+}
+
+#[repr(u16)]
+pub enum TrapCode {
+    Getc = 0x20,  // get character from keyboard, not echoed onto the terminal
+    Out = 0x21,   // output a character
+    Puts = 0x22,  // output a word string
+    In = 0x23,    // get character from keyboard, echoed onto the terminal
+    Putsp = 0x24, // output a byte string
+    Halt = 0x25,  // halt the program
+}
+
+impl TryFrom<u16> for TrapCode {
+    type Error = ();
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            0x20 => Ok(Self::Getc),
+            0x21 => Ok(Self::Out),
+            0x22 => Ok(Self::Puts),
+            0x23 => Ok(Self::In),
+            0x24 => Ok(Self::Putsp),
+            0x25 => Ok(Self::Halt),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The second operand of `ADD`/`AND`: either SR2 or a sign-extended imm5.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Reg(Register),
+    Imm(u16),
+}
+
+/// A decoded instruction word. Offsets and immediates are already
+/// sign-extended to 16 bits so they can be added with `wrapping_add`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    Br {
+        cond: u16,
+        offset: u16,
+    },
+    Add {
+        dr: Register,
+        sr1: Register,
+        operand: Operand,
+    },
+    Ld {
+        dr: Register,
+        offset: u16,
+    },
+    St {
+        sr: Register,
+        offset: u16,
+    },
+    Jsr {
+        offset: u16,
+    },
+    Jsrr {
+        base: Register,
+    },
+    And {
+        dr: Register,
+        sr1: Register,
+        operand: Operand,
+    },
+    Ldr {
+        dr: Register,
+        base: Register,
+        offset: u16,
+    },
+    Str {
+        sr: Register,
+        base: Register,
+        offset: u16,
+    },
+    Rti,
+    Not {
+        dr: Register,
+        sr: Register,
+    },
+    Ldi {
+        dr: Register,
+        offset: u16,
+    },
+    Sti {
+        sr: Register,
+        offset: u16,
+    },
+    Jmp {
+        base: Register,
+    },
+    Res,
+    Lea {
+        dr: Register,
+        offset: u16,
+    },
+    Trap {
+        vector: u8,
+    },
+}
+
+impl Instruction {
+    pub fn opcode(&self) -> OpCode {
+        match self {
+            Instruction::Br { .. } => OpCode::Br,
+            Instruction::Add { .. } => OpCode::Add,
+            Instruction::Ld { .. } => OpCode::Ld,
+            Instruction::St { .. } => OpCode::St,
+            Instruction::Jsr { .. } | Instruction::Jsrr { .. } => OpCode::Jsr,
+            Instruction::And { .. } => OpCode::And,
+            Instruction::Ldr { .. } => OpCode::Ldr,
+            Instruction::Str { .. } => OpCode::Str,
+            Instruction::Rti => OpCode::Rti,
+            Instruction::Not { .. } => OpCode::Not,
+            Instruction::Ldi { .. } => OpCode::Ldi,
+            Instruction::Sti { .. } => OpCode::Sti,
+            Instruction::Jmp { .. } => OpCode::Jmp,
+            Instruction::Res => OpCode::Res,
+            Instruction::Lea { .. } => OpCode::Lea,
+            Instruction::Trap { .. } => OpCode::Trap,
+        }
+    }
+
+    /// The inverse of `decode`. Offsets and immediates are truncated to
+    /// their field width.
+    pub fn encode(&self) -> u16 {
+        let op = (self.opcode() as u16) << 12;
+
+        match *self {
+            Instruction::Br { cond, offset } => op | (cond & 0x7) << 9 | offset & 0x1FF,
+            Instruction::Add { dr, sr1, operand } | Instruction::And { dr, sr1, operand } => {
+                op | field(dr, 9) | field(sr1, 6) | encode_operand(operand)
+            }
+            Instruction::Ld { dr: r, offset }
+            | Instruction::St { sr: r, offset }
+            | Instruction::Ldi { dr: r, offset }
+            | Instruction::Sti { sr: r, offset }
+            | Instruction::Lea { dr: r, offset } => op | field(r, 9) | offset & 0x1FF,
+            Instruction::Jsr { offset } => op | 1 << 11 | offset & 0x7FF,
+            Instruction::Jsrr { base } | Instruction::Jmp { base } => op | field(base, 6),
+            Instruction::Ldr {
+                dr: r,
+                base,
+                offset,
+            }
+            | Instruction::Str {
+                sr: r,
+                base,
+                offset,
+            } => op | field(r, 9) | field(base, 6) | offset & 0x3F,
+            Instruction::Not { dr, sr } => op | field(dr, 9) | field(sr, 6) | 0x3F,
+            Instruction::Rti | Instruction::Res => op,
+            Instruction::Trap { vector } => op | vector as u16,
+        }
+    }
+}
+
+/* register placed in the 3 bits starting at `shift` */
+fn field(reg: Register, shift: u16) -> u16 {
+    (reg as u16 & 0x7) << shift
+}
+
+fn encode_operand(operand: Operand) -> u16 {
+    match operand {
+        Operand::Reg(sr2) => field(sr2, 0),
+        Operand::Imm(imm5) => 1 << 5 | imm5 & 0x1F,
+    }
+}
+
+/* register encoded in the 3 bits starting at `shift` */
+fn reg(word: u16, shift: u16) -> Register {
+    GENERAL_REGISTERS[((word >> shift) & 0x7) as usize]
+}
+
+/* SR2 or imm5, selected by bit 5 */
+fn operand(word: u16) -> Operand {
+    if (word >> 5) & 0x1 == 1 {
+        Operand::Imm(sign_extend(word & 0x1F, 5))
+    } else {
+        Operand::Reg(reg(word, 0))
+    }
+}
+
+/// Decodes a 16-bit word into its opcode and typed operand fields.
+/// Every word decodes; reserved encodings come back as `Res`/`Rti`.
+pub fn decode(word: u16) -> Instruction {
+    let pc_offset9 = sign_extend(word & 0x1FF, 9);
+    let offset6 = sign_extend(word & 0x3F, 6);
+
+    match word >> 12 {
+        0b0000 => Instruction::Br {
+            cond: (word >> 9) & 0x7,
+            offset: pc_offset9,
+        },
+        0b0001 => Instruction::Add {
+            dr: reg(word, 9),
+            sr1: reg(word, 6),
+            operand: operand(word),
+        },
+        0b0010 => Instruction::Ld {
+            dr: reg(word, 9),
+            offset: pc_offset9,
+        },
+        0b0011 => Instruction::St {
+            sr: reg(word, 9),
+            offset: pc_offset9,
+        },
+        0b0100 => {
+            /* bit 11 picks JSR's PCoffset11 over JSRR's base register */
+            if (word >> 11) & 1 == 1 {
+                Instruction::Jsr {
+                    offset: sign_extend(word & 0x7FF, 11),
+                }
+            } else {
+                Instruction::Jsrr { base: reg(word, 6) }
+            }
+        }
+        0b0101 => Instruction::And {
+            dr: reg(word, 9),
+            sr1: reg(word, 6),
+            operand: operand(word),
+        },
+        0b0110 => Instruction::Ldr {
+            dr: reg(word, 9),
+            base: reg(word, 6),
+            offset: offset6,
+        },
+        0b0111 => Instruction::Str {
+            sr: reg(word, 9),
+            base: reg(word, 6),
+            offset: offset6,
+        },
+        0b1000 => Instruction::Rti,
+        0b1001 => Instruction::Not {
+            dr: reg(word, 9),
+            sr: reg(word, 6),
+        },
+        0b1010 => Instruction::Ldi {
+            dr: reg(word, 9),
+            offset: pc_offset9,
+        },
+        0b1011 => Instruction::Sti {
+            sr: reg(word, 9),
+            offset: pc_offset9,
+        },
+        0b1100 => Instruction::Jmp { base: reg(word, 6) },
+        0b1101 => Instruction::Res,
+        0b1110 => Instruction::Lea {
+            dr: reg(word, 9),
+            offset: pc_offset9,
+        },
+        _ => Instruction::Trap {
+            vector: (word & 0xFF) as u8,
+        },
+    }
+}
+
+/// `base + offset` as LDR and STR compute it, wrapping at xFFFF. The
+/// offset is a sign-extended field from `decode`.
+pub fn effective_address(base: u16, offset: u16) -> u16 {
+    base.wrapping_add(offset)
+}
+
+/// Where a PC-relative offset in the instruction at `pc` points. Offsets
+/// count from the incremented PC, so this is `pc + 1 + offset`, wrapping:
+/// a negative offset near x0000 reaches the top of memory, as on hardware
+/// (`LEA R0, #-3` at x0001 gives xFFFF). BR, JSR, LD, LDI, LEA, ST and STI
+/// all resolve their operand through here.
+pub fn pc_relative(pc: u16, offset: u16) -> u16 {
+    effective_address(pc.wrapping_add(1), offset)
+}
+
+pub fn sign_extend(x: u16, bit_count: u8) -> u16 {
+    if ((x >> (bit_count - 1)) & 1) == 1 {
+        x | (0xFFFF << bit_count)
+    } else {
+        x
+    }
+}
+
+/// The flag an instruction writing `value` leaves set: Z for zero, N when
+/// bit 15 is set, P otherwise.
+pub fn condition_for(value: u16) -> ConditionFlag {
+    if value == 0 {
+        ConditionFlag::Zro
+    } else if value >> 15 & 1 == 1 {
+        ConditionFlag::Neg
+    } else {
+        ConditionFlag::Pos
+    }
+}
+
+/// Reads a word as the two's complement value LC-3 arithmetic treats it as.
+pub fn to_signed(word: u16) -> i16 {
+    word as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_add_with_register_and_immediate_operands() {
+        // ADD R1, R2, R3
+        assert_eq!(
+            decode(0x1283),
+            Instruction::Add {
+                dr: Register::R1,
+                sr1: Register::R2,
+                operand: Operand::Reg(Register::R3),
+            }
+        );
+        // ADD R0, R0, #-1
+        assert_eq!(
+            decode(0x103F),
+            Instruction::Add {
+                dr: Register::R0,
+                sr1: Register::R0,
+                operand: Operand::Imm(0xFFFF),
+            }
+        );
+    }
+
+    #[test]
+    fn decodes_pc_relative_offsets_sign_extended() {
+        // BRnz #-2
+        assert_eq!(
+            decode(0x0DFE),
+            Instruction::Br {
+                cond: 0b110,
+                offset: 0xFFFE,
+            }
+        );
+        // LD R3, #5
+        assert_eq!(
+            decode(0x2605),
+            Instruction::Ld {
+                dr: Register::R3,
+                offset: 5,
+            }
+        );
+        // JSR #-1024
+        assert_eq!(decode(0x4C00), Instruction::Jsr { offset: 0xFC00 });
+    }
+
+    #[test]
+    fn decodes_base_register_forms() {
+        // LDR R4, R6, #-32
+        assert_eq!(
+            decode(0x69A0),
+            Instruction::Ldr {
+                dr: Register::R4,
+                base: Register::R6,
+                offset: 0xFFE0,
+            }
+        );
+        // STR R0, R5, #31
+        assert_eq!(
+            decode(0x715F),
+            Instruction::Str {
+                sr: Register::R0,
+                base: Register::R5,
+                offset: 31,
+            }
+        );
+        assert_eq!(decode(0xC1C0), Instruction::Jmp { base: Register::R7 });
+        assert_eq!(decode(0x4080), Instruction::Jsrr { base: Register::R2 });
+        // NOT R5, R1
+        assert_eq!(
+            decode(0x9A7F),
+            Instruction::Not {
+                dr: Register::R5,
+                sr: Register::R1,
+            }
+        );
+    }
+
+    #[test]
+    fn decodes_traps_and_unimplemented_opcodes() {
+        assert_eq!(decode(0xF025), Instruction::Trap { vector: 0x25 });
+        assert_eq!(decode(0x8000), Instruction::Rti);
+        assert_eq!(decode(0xD123), Instruction::Res);
+    }
+
+    #[test]
+    fn encode_inverts_decode_for_canonical_words() {
+        for word in [
+            0x1283, 0x103F, 0x0DFE, 0x2605, 0x4C00, 0x69A0, 0x715F, 0xC1C0, 0x4080, 0x9A7F, 0xF025,
+            0xE1FD,
+        ] {
+            assert_eq!(decode(word).encode(), word, "x{:04X}", word);
+        }
+    }
+
+    #[test]
+    fn effective_addresses_add_signed_offsets_and_wrap() {
+        assert_eq!(effective_address(0x4000, 5), 0x4005);
+        assert_eq!(effective_address(0x4000, sign_extend(0x3F, 6)), 0x3FFF);
+        assert_eq!(effective_address(0xFFFF, 1), 0x0000);
+        assert_eq!(effective_address(0x0000, sign_extend(0x3F, 6)), 0xFFFF);
+        assert_eq!(effective_address(0x0002, sign_extend(0x20, 6)), 0xFFE2);
+    }
+
+    #[test]
+    fn pc_relative_counts_from_the_incremented_pc() {
+        assert_eq!(pc_relative(0x3000, 0), 0x3001);
+        assert_eq!(pc_relative(0x3000, sign_extend(0x1FF, 9)), 0x3000);
+        assert_eq!(pc_relative(0x3000, sign_extend(0x100, 9)), 0x2F01);
+        assert_eq!(pc_relative(0x0001, sign_extend(0x1FD, 9)), 0xFFFF);
+        assert_eq!(pc_relative(0xFFFF, 0), 0x0000);
+        assert_eq!(pc_relative(0xFFFE, 0xFF), 0x00FE);
+    }
+
+    #[test]
+    fn every_top_nibble_names_its_opcode() {
+        let expected = [
+            OpCode::Br,
+            OpCode::Add,
+            OpCode::Ld,
+            OpCode::St,
+            OpCode::Jsr,
+            OpCode::And,
+            OpCode::Ldr,
+            OpCode::Str,
+            OpCode::Rti,
+            OpCode::Not,
+            OpCode::Ldi,
+            OpCode::Sti,
+            OpCode::Jmp,
+            OpCode::Res,
+            OpCode::Lea,
+            OpCode::Trap,
+        ];
+
+        for (nibble, &opcode) in expected.iter().enumerate() {
+            let nibble = nibble as u16;
+            assert_eq!(OpCode::from_instruction(nibble << 12), opcode);
+            assert_eq!(OpCode::from_instruction(nibble << 12 | 0x0FFF), opcode);
+            assert_eq!(OpCode::try_from(nibble), Ok(opcode));
+            assert_eq!(opcode as u16, nibble);
+        }
+        assert_eq!(OpCode::try_from(16), Err(()));
+        /* JSRR shares JSR's opcode */
+        assert_eq!(OpCode::from_instruction(0x4080), OpCode::Jsr);
+    }
+
+    #[test]
+    fn condition_for_follows_the_sign_bit() {
+        assert_eq!(condition_for(0), ConditionFlag::Zro);
+        assert_eq!(condition_for(0x8000), ConditionFlag::Neg);
+        assert_eq!(condition_for(0xFFFF), ConditionFlag::Neg);
+        assert_eq!(condition_for(1), ConditionFlag::Pos);
+        assert_eq!(condition_for(0x7FFF), ConditionFlag::Pos);
+    }
+
+    #[test]
+    fn bit_11_tells_jsr_from_jsrr() {
+        assert_eq!(decode(0x4802), Instruction::Jsr { offset: 2 });
+        assert_eq!(decode(0x4FFF), Instruction::Jsr { offset: 0xFFFF });
+        assert_eq!(decode(0x41C0), Instruction::Jsrr { base: Register::R7 });
+        assert_eq!(decode(0x4802).opcode(), decode(0x41C0).opcode());
+    }
+}
@@ -0,0 +1,40 @@
+pub mod analysis;
+pub mod asm;
+pub mod bitset;
+#[cfg(feature = "cache")]
+pub mod cache;
+pub mod cfg;
+pub mod debugger;
+pub mod device;
+pub mod diff;
+pub mod disasm;
+pub mod encode;
+pub mod error;
+pub mod input;
+pub mod instruction;
+pub mod os;
+pub mod output;
+pub mod programs;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod vm;
+
+pub use analysis::DecodeWarning;
+pub use asm::asm_one;
+pub use bitset::BitSet;
+pub use cfg::{BasicBlock, Cfg, EdgeKind};
+pub use debugger::Debugger;
+pub use device::MmioDevice;
+pub use diff::StateDiff;
+pub use error::{AsmError, LoadError, ParseError, VmError};
+pub use input::{Input, LineInput, TerminalInput};
+pub use instruction::{
+    condition_for, decode, effective_address, pc_relative, sign_extend, to_signed, ConditionFlag,
+    Instruction, OpCode, Operand, Register, TrapCode, GENERAL_REGISTERS,
+};
+pub use output::Tee;
+pub use vm::{
+    run_captured, run_fuzz, Access, AccessKind, ByteOrder, CostModel, FaultAction, MemInit,
+    Options, Overflow, ReservedOpPolicy, RunReport, RunState, Segment, StepResult, VmSnapshot, DDR,
+    DEFAULT_ORIGIN, DSR, KBDR, KBSR, MCR, MEMORY_MAX, RNG, TRAP_VECTOR_BASE, VM,
+};
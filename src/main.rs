@@ -1,492 +1,259 @@
-use std::io::Write;
+use std::fs;
+use std::io::{self, IsTerminal};
 use std::{env, process};
 
-use crossterm::event::{self, Event, KeyCode};
-use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
-
-const MEMORY_MAX: usize = 1 << 16;
-
-#[repr(u16)]
-#[derive(Debug, Copy, Clone)]
-enum Register {
-    R0,
-    R1,
-    R2,
-    R3,
-    R4,
-    R5,
-    R6,
-    R7,
-    Pc,
-    Cond,
-    Count,
+use lc3_vm::debugger::{self, Debugger};
+use lc3_vm::{programs, LoadError, Options, Register, Tee, VmError, VmSnapshot, MEMORY_MAX, VM};
+
+#[derive(Default)]
+struct Cli {
+    example: Option<String>,
+    debug: bool,
+    exit_code: bool,
+    crlf: bool,
+    os: bool,
+    dump_on_halt: bool,
+    cache: bool,
+    disassemble: Option<String>,
+    resume: Option<String>,
+    max_cycles: Option<u64>,
+    save_on_exit: Option<String>,
+    tee: Option<String>,
+    symbols: Vec<String>,
+    images: Vec<String>,
 }
 
-impl TryFrom<u16> for Register {
-    type Error = ();
+fn main() {
+    let cli = parse_args(env::args().skip(1));
 
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(Register::R0),
-            1 => Ok(Register::R1),
-            2 => Ok(Register::R2),
-            3 => Ok(Register::R3),
-            4 => Ok(Register::R4),
-            5 => Ok(Register::R5),
-            6 => Ok(Register::R6),
-            7 => Ok(Register::R7),
-            8 => Ok(Register::Pc),
-            9 => Ok(Register::Cond),
-            _ => Err(()),
-        }
+    if let Some(filename) = &cli.disassemble {
+        disassemble(filename);
+        return;
     }
-}
 
-#[repr(u16)]
-enum OpCode {
-    Br,   /* Branch */
-    Add,  /* add */
-    Ld,   /* load */
-    St,   /* store */
-    Jsr,  /* jump reguster */
-    And,  /* bitwise and */
-    Ldr,  /* load register */
-    Str,  /* store register */
-    Rti,  /* unused */
-    Not,  /* bitwise not */
-    Ldi,  /* load indirect */
-    Sti,  /* store indirect */
-    Jmp,  /* jump */
-    Res,  /* reserved (unused) */
-    Lea,  /* load effective address */
-    Trap, /* execute trap */
-}
+    if cli.example.is_none() && cli.images.is_empty() && cli.resume.is_none() {
+        usage();
+    }
 
-impl TryFrom<u16> for OpCode {
-    type Error = ();
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
-        match value {
-            0b0000 => Ok(Self::Br),
-            0b0001 => Ok(Self::Add),
-            0b0010 => Ok(Self::Ld),
-            0b0011 => Ok(Self::St),
-            0b0100 => Ok(Self::Jsr),
-            0b0101 => Ok(Self::And),
-            0b0110 => Ok(Self::Ldr),
-            0b0111 => Ok(Self::Str),
-            0b1000 => Ok(Self::Rti),
-            0b1001 => Ok(Self::Not),
-            0b1010 => Ok(Self::Ldi),
-            0b1011 => Ok(Self::Sti),
-            0b1100 => Ok(Self::Jmp),
-            0b1101 => Ok(Self::Res),
-            0b1110 => Ok(Self::Lea),
-            0b1111 => Ok(Self::Trap),
-            _ => Err(()),
+    let snapshot = cli.resume.as_deref().map(|filename| {
+        let snapshot = VmSnapshot::read(filename).unwrap_or_else(|err| {
+            eprintln!("Failed to load snapshot: {}: {}", filename, err);
+            process::exit(1);
+        });
+        // its PC is past the HALT, so running on would execute whatever follows
+        if snapshot.halted() {
+            eprintln!(
+                "Failed to resume: {}: the program had already halted",
+                filename
+            );
+            process::exit(1);
         }
+        snapshot
+    });
+
+    let mut vm = VM::with_options(Options {
+        // translating only makes sense for a terminal; piped output stays byte-exact
+        crlf_output: cli.crlf && io::stdout().is_terminal(),
+        // only the debugger can step back, so only it pays for the journal
+        undo_depth: if cli.debug { 4096 } else { 0 },
+        // let the debugger prompt for keys instead of the program blocking
+        pause_on_input: cli.debug,
+        memory_size: snapshot
+            .as_ref()
+            .map_or(MEMORY_MAX, VmSnapshot::memory_size),
+        ..Options::default()
+    });
+
+    // carry on exactly where the saved run stopped, PC included
+    if let Some(snapshot) = &snapshot {
+        vm.restore(snapshot);
     }
-}
-
-#[repr(u16)]
-enum ConditionFlag {
-    Pos = 1 << 0, /* P */
-    Zro = 1 << 1, /* Z */
-    Neg = 1 << 2, /* N */
-}
-
-#[repr(u16)]
-enum TrapCode {
-    Getc = 0x20,  // get character from keyboard, not echoed onto the terminal
-    Out = 0x21,   // output a character
-    Puts = 0x22,  // output a word string
-    In = 0x23,    // get character from keyboard, echoed onto the terminal
-    Putsp = 0x24, // output a byte string
-    Halt = 0x25,  // halt the program
-}
 
-impl TryFrom<u16> for TrapCode {
-    type Error = ();
-
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
-        match value {
-            0x20 => Ok(Self::Getc),
-            0x21 => Ok(Self::Out),
-            0x22 => Ok(Self::Puts),
-            0x23 => Ok(Self::In),
-            0x24 => Ok(Self::Putsp),
-            0x25 => Ok(Self::Halt),
-            _ => Err(()),
+    if let Some(filename) = &cli.tee {
+        match fs::File::create(filename) {
+            Ok(log) => vm.set_output(Tee::new(io::stdout(), log)),
+            Err(err) => {
+                eprintln!("Failed to create log: {}: {}", filename, err);
+                process::exit(1);
+            }
         }
     }
-}
-
-const REGISTER_COUNT: usize = Register::Count as usize;
 
-struct VM {
-    memory: [u16; MEMORY_MAX],
-    registers: [u16; REGISTER_COUNT],
-}
+    if cli.os {
+        vm.install_default_os();
+    }
 
-impl VM {
-    pub fn new() -> Self {
-        Self {
-            memory: [0; MEMORY_MAX],
-            registers: [0; REGISTER_COUNT],
+    if let Some(name) = &cli.example {
+        match programs::example(name) {
+            Some(words) => vm.load_words(programs::ORIGIN, &words),
+            None => {
+                eprintln!(
+                    "unknown example {:?}; available: {}",
+                    name,
+                    programs::EXAMPLES.join(", ")
+                );
+                process::exit(2);
+            }
         }
     }
 
-    pub fn run(&mut self) {
-        // since exacly one condition flag should be set at any given time, set the Z flag
-        self.set_register(Register::Cond, ConditionFlag::Zro as u16);
-        // set the PC to starting position 0x3000 is the default
-        self.set_register(Register::Pc, 0x3000);
-
-        loop {
-            /* mem red and advance pc */
-            let pc = self.get_register(Register::Pc);
-            let instr: u16 = self.mem_read(pc);
-            self.set_register(Register::Pc, pc.wrapping_add(1));
-
-            let op = match OpCode::try_from(instr >> 12) {
-                Ok(code) => code,
-                Err(_) => break,
-            };
-
-            match op {
-                OpCode::Add => {
-                    /* destination register */
-                    let r0 = Register::try_from((instr >> 9) & 0x7).unwrap();
-                    /* first operand (SR1) */
-                    let r1 = Register::try_from((instr >> 6) & 0x7).unwrap();
-                    /* where we are in immediate mode */
-                    let imm_flag = (instr >> 5) & 0x1;
-
-                    if imm_flag == 1 {
-                        let imm5 = sign_extend(instr & 0x1F, 5);
-                        let result = self.get_register(r1).wrapping_add(imm5);
-                        self.set_register(r0, result);
-                    } else {
-                        let r2 = Register::try_from(instr & 0x7).unwrap();
-                        let result = self.get_register(r1).wrapping_add(self.get_register(r2));
-                        self.set_register(r0, result);
-                    }
-
-                    self.update_flags(r0);
-                }
-                OpCode::And => {
-                    /* destination register */
-                    let r0 = Register::try_from((instr >> 9) & 0x7).unwrap();
-                    /* first operand (SR1) */
-                    let r1 = Register::try_from((instr >> 6) & 0x7).unwrap();
-                    /* where we are in immediate mode */
-                    let imm_flag = (instr >> 5) & 0x1;
-
-                    let result = if imm_flag == 1 {
-                        let imm5 = sign_extend(instr & 0x1F, 5);
-                        self.get_register(r1) & imm5
-                    } else {
-                        let r2 = Register::try_from(instr & 0x7).unwrap();
-                        self.get_register(r1) & self.get_register(r2)
-                    };
-
-                    self.set_register(r0, result);
-                    self.update_flags(r0);
-                }
-                OpCode::Not => {
-                    /* destination register */
-                    let r0 = Register::try_from((instr >> 9) & 0x7).unwrap();
-                    /* first operand (SR1) */
-                    let r1 = Register::try_from((instr >> 6) & 0x7).unwrap();
-
-                    self.set_register(r0, !self.get_register(r1));
-                    self.update_flags(r0);
-                }
-                OpCode::Br => {
-                    let pc_offset = sign_extend(instr & 0x1FF, 9);
-                    let cond_flag = (instr >> 9) & 0x7;
-
-                    if self.get_register(Register::Cond) == cond_flag {
-                        let pc = self.get_register(Register::Pc);
-                        self.set_register(Register::Pc, pc.wrapping_add(pc_offset));
-                    }
-                }
-                OpCode::Jmp => {
-                    let base_r = Register::try_from((instr >> 6) & 0x7).unwrap();
-                    let target_address = self.get_register(base_r);
-                    self.set_register(Register::Pc, target_address);
-                }
-                OpCode::Jsr => {
-                    /* first save incremented Pc into R7 */
-                    let pc = self.get_register(Register::Pc);
-                    self.set_register(Register::R7, pc);
-
-                    let long_flag = (instr >> 11) & 1;
-
-                    if long_flag == 1 {
-                        // JSR: PC-relative offset
-                        let offset = sign_extend(instr & 0x7FF, 11);
-                        let new_pc = pc.wrapping_add(offset);
-                        self.set_register(Register::Pc, new_pc);
-                    } else {
-                        // JSRR: Base register
-                        let r1 = Register::try_from((instr >> 6) & 0x7).unwrap();
-                        self.set_register(Register::Pc, self.get_register(r1));
-                    }
-                }
-                OpCode::Ld => {
-                    let r0 = Register::try_from((instr >> 9) & 0x7).unwrap();
-                    let pc_offset = sign_extend(instr & 0x1FF, 9);
-                    let pc = self.get_register(Register::Pc);
-                    let value = self.mem_read(pc.wrapping_add(pc_offset));
-                    self.set_register(r0, value);
-                    self.update_flags(r0);
-                }
-                OpCode::Ldi => {
-                    /* destination register */
-                    let r0 = Register::try_from((instr >> 9) & 0x7).unwrap();
-                    /* PcOffset 9*/
-                    let pc_offset = sign_extend(instr & 0x1FF, 9);
-                    /* add pc_offset to the current PC, look at that memory location to get the final address */
-
-                    let pc = self.get_register(Register::Pc);
-                    // Read the address from memory at (PC + offset)
-                    let addr = self.mem_read(pc.wrapping_add(pc_offset));
-                    // Read the actual value from that address
-                    let val = self.mem_read(addr);
-
-                    self.set_register(r0, val);
-                    self.update_flags(r0);
-                }
-                OpCode::Ldr => {
-                    /* DR */
-                    let r0 = Register::try_from((instr >> 9) & 0x7).unwrap();
-                    /* offset6 */
-                    let offset = sign_extend(instr & 0x3F, 6);
-                    /* BaseR */
-                    let base_r = Register::try_from((instr >> 6) & 0x7).unwrap();
-
-                    /* Add offse to content of baser register */
-                    let address = self.get_register(base_r).wrapping_add(offset);
-
-                    /* Get the content in memory of address */
-                    let value = self.mem_read(address);
-
-                    /*Load vlaue into DR*/
-                    self.set_register(r0, value);
-
-                    /* Update flags with the content */
-                    self.update_flags(r0);
-                }
-                OpCode::Lea => {
-                    /* DR */
-                    let r0 = Register::try_from((instr >> 9) & 0x7).unwrap();
-
-                    /*PcOffset9*/
-                    let pc_offset = sign_extend(instr & 0x1FF, 9);
-
-                    /* Incremented PC */
-                    let pc = self.get_register(Register::Pc);
-
-                    /*Address*/
-                    let address = pc.wrapping_add(pc_offset);
-
-                    /*This address is loaded into DR*/
-                    self.set_register(r0, address);
-
-                    /*The conditions are set based on the value loaded */
-                    self.update_flags(r0);
-                }
-                OpCode::St => {
-                    /*SR*/
-                    let r0 = Register::try_from((instr >> 9) & 0x7).unwrap();
-
-                    /*PCoffset9*/
-                    let pc_offset = sign_extend(instr & 0x1FF, 9);
-
-                    /*Content of the register SR*/
-                    let value = self.get_register(r0);
-
-                    /* Memory Address */
-                    let pc = self.get_register(Register::Pc);
-                    let address = pc.wrapping_add(pc_offset);
-
-                    self.mem_write(address, value);
-                }
-                OpCode::Sti => {
-                    /*SR*/
-                    let r0 = Register::try_from((instr >> 9) & 0x7).unwrap();
-                    /*PCoffset9*/
-                    let pc_offset = sign_extend(instr & 0x1FF, 9);
-
-                    /*Content of the register SR*/
-                    let value = self.get_register(r0);
-
-                    /* Memory Address */
-                    let pc = self.get_register(Register::Pc);
-                    let address = pc.wrapping_add(pc_offset);
-
-                    self.mem_write(self.mem_read(address), value);
-                }
-                OpCode::Str => {
-                    /*SR*/
-                    let r0 = Register::try_from((instr >> 9) & 0x7).unwrap();
-
-                    /*BaseR*/
-                    let base_r = Register::try_from((instr >> 6) & 0x7).unwrap();
-
-                    /*offset6*/
-                    let base_offset = sign_extend(instr & 0x3F, 6);
-
-                    /* memory address*/
-                    let address = self.get_register(base_r).wrapping_add(base_offset);
-
-                    self.mem_write(address, self.get_register(r0));
-                }
-                OpCode::Trap => {
-                    self.set_register(Register::R7, self.get_register(Register::Pc));
-                    let trap = TrapCode::try_from(instr & 0xFF).unwrap();
-                    match trap {
-                        TrapCode::Getc => {
-                            let ch = getchar_raw();
-                            self.set_register(Register::R0, ch as u16);
-                            self.update_flags(Register::R0);
-                        }
-                        TrapCode::Out => {
-                            let ch = self.get_register(Register::R0) as u8 as char;
-                            print!("{}", ch);
-                            std::io::stdout().flush().unwrap();
-                        }
-                        TrapCode::Puts => {
-                            let mut address = self.get_register(Register::R0);
-                            loop {
-                                let ch = self.mem_read(address);
-
-                                if ch == 0 {
-                                    break;
-                                }
-
-                                print!("{}", ch as u8 as char);
-                                address = address.wrapping_add(1)
-                            }
-
-                            std::io::stdout().flush().unwrap();
-                        }
-                        TrapCode::In => {
-                            print!("Enter a character: ");
-                            std::io::stdout().flush().unwrap(); // Make sure prompt appears before input
-
-                            let ch = getchar_raw(); // Read unbuffered character
-                            print!("{}", ch); // Echo back
-                            std::io::stdout().flush().unwrap(); // Flush echo immediately
-
-                            self.set_register(Register::R0, ch as u16);
-                            self.update_flags(Register::R0);
-                        }
-                        TrapCode::Putsp => {
-                            /*one char per byte (two bytes per word) here we need to swap back to
-                             * big endian format*/
-                            let mut address = self.get_register(Register::R0);
-
-                            loop {
-                                let word = self.mem_read(address);
+    if cli.cache && !cfg!(feature = "cache") {
+        eprintln!("--cache needs lc3 built with the cache feature");
+        process::exit(2);
+    }
 
-                                if word == 0 {
-                                    break;
-                                }
+    for filename in &cli.images {
+        if let Err(err) = load(&mut vm, filename, cli.cache) {
+            load_failed(filename, &err);
+        }
+    }
 
-                                let char1 = (word & 0xFF) as u8;
-                                print!("{}", char1 as char);
+    if cli.debug {
+        let mut debugger = Debugger::new(vm);
 
-                                let char2 = (word >> 8) as u8;
-                                if char2 != 0 {
-                                    print!("{}", char2 as char);
-                                }
-                                address = address.wrapping_add(1);
-                            }
-                            std::io::stdout().flush().unwrap();
-                        }
-                        TrapCode::Halt => {
-                            println!("HALT");
-                            break;
-                        }
-                    }
+        for filename in &cli.symbols {
+            match fs::read_to_string(filename) {
+                Ok(text) => debugger.load_symbols(debugger::parse_symbol_table(&text)),
+                Err(err) => {
+                    eprintln!("Failed to load symbols: {}: {}", filename, err);
+                    process::exit(1);
                 }
-                OpCode::Res | OpCode::Rti => break,
             }
         }
-    }
-
-    fn set_register(&mut self, reg: Register, value: u16) {
-        self.registers[reg as usize] = value;
-    }
 
-    fn mem_read(&self, address: u16) -> u16 {
-        todo!()
+        if let Err(err) = debugger.repl() {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+        return;
     }
 
-    fn mem_write(&mut self, address: u16, value: u16) {
-        todo!()
+    // a partial run stops at the limit, leaving a snapshot to resume from
+    let result = match cli.max_cycles {
+        Some(limit) => vm.expect_halt(limit).map(|_| vm.get_register(Register::R0)),
+        None => vm.run(),
+    };
+    if cli.dump_on_halt {
+        eprint!("{}", vm.dump_registers());
     }
-
-    fn get_register(&self, reg: Register) -> u16 {
-        self.registers[reg as usize]
+    if let Some(filename) = &cli.save_on_exit {
+        if let Err(err) = vm.snapshot().write(filename) {
+            eprintln!("Failed to save snapshot: {}: {}", filename, err);
+            process::exit(1);
+        }
     }
 
-    fn update_flags(&mut self, r: Register) {
-        let val = self.get_register(r);
-        let flag = if val == 0 {
-            ConditionFlag::Zro
-        } else if val >> 15 & 1 == 1 {
-            ConditionFlag::Neg
-        } else {
-            ConditionFlag::Pos
-        };
-
-        self.set_register(Register::Cond, flag as u16);
+    match result {
+        // R0 at HALT becomes the exit status, saturating at 255
+        Ok(code) if cli.exit_code => process::exit(code.min(255) as i32),
+        Ok(_) => {}
+        Err(VmError::CycleLimitExceeded(limit)) => {
+            eprintln!("stopped after {} instructions at x{:04X}", limit, vm.pc())
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
     }
 }
-fn main() {
-    let args: Vec<String> = env::args().collect();
 
-    if args.len() < 2 {
-        eprintln!("lc3 [image-file1] ...\n");
-        process::exit(2);
-    }
+fn usage() -> ! {
+    eprintln!("lc3 [--exit-code] [--crlf] [--os] [--dump-on-halt] [image-file1] ...");
+    eprintln!(
+        "lc3 [--resume snapshot] [--save-on-exit snapshot] [--max-cycles n] [image-file1] ..."
+    );
+    eprintln!("lc3 [--tee log-file] [image-file1] ...");
+    eprintln!("lc3 --cache [image-file1] ...        (keeps parsed images in <file>.cache)");
+    eprintln!("lc3 --example <{}>", programs::EXAMPLES.join("|"));
+    eprintln!("lc3 --debug [--symbols file.sym] [image-file1] ...");
+    eprintln!("lc3 --disassemble <image-file>");
+    eprintln!("use - as a file name to read an image from stdin");
+    eprintln!("files ending in .hex are read as text: .ORIG, one hex word per line, .END\n");
+    process::exit(2);
+}
 
-    for filename in &args[1..] {
-        if !read_image(filename) {
-            eprintln!("Failed to load image: {}", filename);
-            process::exit(1);
+fn parse_args(mut args: impl Iterator<Item = String>) -> Cli {
+    let mut cli = Cli::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--example" => cli.example = Some(args.next().unwrap_or_else(|| usage())),
+            "--debug" => cli.debug = true,
+            "--exit-code" => cli.exit_code = true,
+            "--crlf" => cli.crlf = true,
+            "--os" => cli.os = true,
+            "--dump-on-halt" => cli.dump_on_halt = true,
+            "--cache" => cli.cache = true,
+            "--disassemble" => cli.disassemble = Some(args.next().unwrap_or_else(|| usage())),
+            "--resume" => cli.resume = Some(args.next().unwrap_or_else(|| usage())),
+            "--max-cycles" => {
+                let limit = args.next().and_then(|limit| limit.parse().ok());
+                cli.max_cycles = Some(limit.unwrap_or_else(|| usage()));
+            }
+            "--save-on-exit" => cli.save_on_exit = Some(args.next().unwrap_or_else(|| usage())),
+            "--tee" => cli.tee = Some(args.next().unwrap_or_else(|| usage())),
+            "--symbols" => cli.symbols.push(args.next().unwrap_or_else(|| usage())),
+            _ => cli.images.push(arg),
         }
     }
 
-    let mut vm = VM::new();
-    vm.run();
+    cli
 }
 
-pub fn read_image(filename: &str) -> bool {
-    todo!()
-}
+/* print a listing of one image without running it */
+fn disassemble(filename: &str) {
+    let mut vm = VM::new();
 
-pub fn sign_extend(x: u16, bit_count: u8) -> u16 {
-    if ((x >> (bit_count - 1)) & 1) == 1 {
-        x | (0xFFFF << bit_count)
-    } else {
-        x
+    match load(&mut vm, filename, false) {
+        Ok(origin) => {
+            let len = vm.segments().last().map_or(0, |segment| segment.len);
+            print!("{}", vm.listing(origin, len));
+        }
+        Err(err) => load_failed(filename, &err),
     }
 }
 
-fn getchar_raw() -> char {
-    enable_raw_mode().unwrap();
+/* report a load error with a hint at what to do about it */
+fn load_failed(filename: &str, err: &LoadError) -> ! {
+    eprintln!("Failed to load image: {}: {}", filename, err);
 
-    let ch = loop {
-        if let Event::Key(key_event) = event::read().unwrap() {
-            if let KeyCode::Char(c) = key_event.code {
-                break c;
-            }
+    let hint = match err {
+        LoadError::NotFound => Some("check the path for typos"),
+        LoadError::IsDirectory => Some("name an image file inside the directory instead"),
+        LoadError::PermissionDenied => Some("check that the file is readable by you"),
+        LoadError::BadFormat(_) => {
+            Some("expected an LC-3 object file: a big-endian origin word, then the program")
         }
+        _ => None,
     };
+    if let Some(hint) = hint {
+        eprintln!("hint: {}", hint);
+    }
+    process::exit(1);
+}
+
+/* `cached` only applies to object files; text images and stdin are parsed every time */
+fn load(vm: &mut VM, filename: &str, cached: bool) -> Result<u16, LoadError> {
+    if filename.ends_with(".hex") {
+        return vm.load_hex_text(&fs::read_to_string(filename)?);
+    }
+    #[cfg(feature = "cache")]
+    if cached && filename != "-" {
+        return vm.read_image_cached(filename);
+    }
+    #[cfg(not(feature = "cache"))]
+    let _ = cached;
+    if filename != "-" {
+        return vm.read_image(filename);
+    }
+
+    let stdin = io::stdin();
+    if stdin.is_terminal() {
+        return Err(LoadError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "stdin is a terminal; pipe or redirect an image into it",
+        )));
+    }
 
-    disable_raw_mode().unwrap();
-    ch
+    vm.read_image_from(stdin.lock())
 }
@@ -0,0 +1,146 @@
+//! A minimal LC-3 operating system: a trap vector table and the standard
+//! service routines written in LC-3 code against the memory-mapped
+//! devices, for running with `Options::vectored_traps`. Installed by
+//! `VM::install_default_os`.
+
+use crate::instruction::TrapCode;
+use crate::programs::stringz;
+
+/// Load address of the service routines.
+pub const ROUTINES_ORIGIN: u16 = 0x0200;
+
+/* entry points within ROUTINES */
+const GETC: u16 = 0x0200;
+const OUT: u16 = 0x0204;
+const PUTS: u16 = 0x020A;
+const IN: u16 = 0x0218;
+const PUTSP: u16 = 0x0220;
+const BAD_TRAP: u16 = 0x0245;
+const HALT: u16 = 0x0247;
+
+/// The 256-entry trap vector table. Vectors without a routine lead to one
+/// that reports the bad trap and halts.
+pub fn trap_table() -> Vec<u16> {
+    let mut table = vec![BAD_TRAP; 0x100];
+
+    for (trap, routine) in [
+        (TrapCode::Getc, GETC),
+        (TrapCode::Out, OUT),
+        (TrapCode::Puts, PUTS),
+        (TrapCode::In, IN),
+        (TrapCode::Putsp, PUTSP),
+        (TrapCode::Halt, HALT),
+    ] {
+        table[trap as usize] = routine;
+    }
+
+    table
+}
+
+/// The service routines, to be loaded at `ROUTINES_ORIGIN`. HALT stops the
+/// machine by clearing the clock enable bit of the MCR at xFFFE.
+pub fn routines() -> Vec<u16> {
+    let mut words = ROUTINES.to_vec();
+    /* the LEAs in ROUTINES expect these strings in this order right after it */
+    words.extend(stringz("Enter a character: "));
+    words.extend(stringz("HALT\n"));
+    words.extend(stringz("unknown trap\n"));
+    words
+}
+
+const ROUTINES: [u16; 94] = [
+    0xA04C, // x0200 GETC: LDI R0, KBSRP
+    0x05FE, // x0201 BRz GETC
+    0xA04B, // x0202 LDI R0, KBDRP
+    0xC1C0, // x0203 RET
+    0x324F, // x0204 OUT: ST R1, OUTR1
+    0xA249, // x0205 OUTW: LDI R1, DSRP
+    0x05FE, // x0206 BRz OUTW
+    0xB048, // x0207 STI R0, DDRP
+    0x224B, // x0208 LD R1, OUTR1
+    0xC1C0, // x0209 RET
+    0x304B, // x020A PUTS: ST R0, SAVER0
+    0x324B, // x020B ST R1, SAVER1
+    0x3E4F, // x020C ST R7, SAVER7
+    0x1220, // x020D ADD R1, R0, #0
+    0x6040, // x020E PUTSL: LDR R0, R1, #0
+    0x0404, // x020F BRz PUTSD
+    0x4FF3, // x0210 JSR OUT
+    0x1261, // x0211 ADD R1, R1, #1
+    0x03FB, // x0212 BRp PUTSL
+    0x09FA, // x0213 BRn PUTSL
+    0x2041, // x0214 PUTSD: LD R0, SAVER0
+    0x2241, // x0215 LD R1, SAVER1
+    0x2E45, // x0216 LD R7, SAVER7
+    0xC1C0, // x0217 RET
+    0x3E3C, // x0218 IN: ST R7, INR7
+    0xE044, // x0219 LEA R0, PROMPT
+    0x4FEF, // x021A JSR PUTS
+    0x4FE4, // x021B JSR GETC
+    0x4FE7, // x021C JSR OUT
+    0x2E37, // x021D LD R7, INR7
+    0x1020, // x021E ADD R0, R0, #0
+    0xC1C0, // x021F RET
+    0x3035, // x0220 PUTSP: ST R0, SAVER0
+    0x3235, // x0221 ST R1, SAVER1
+    0x3435, // x0222 ST R2, SAVER2
+    0x3635, // x0223 ST R3, SAVER3
+    0x3835, // x0224 ST R4, SAVER4
+    0x3A35, // x0225 ST R5, SAVER5
+    0x3E35, // x0226 ST R7, SAVER7
+    0x1220, // x0227 ADD R1, R0, #0
+    0x6440, // x0228 PPWORD: LDR R2, R1, #0
+    0x2628, // x0229 LD R3, LOWBYTE
+    0x5083, // x022A AND R0, R2, R3
+    0x0411, // x022B BRz PPDONE
+    0x4FD7, // x022C JSR OUT
+    0x5020, // x022D AND R0, R0, #0
+    0x2624, // x022E LD R3, BIT8
+    0x1821, // x022F ADD R4, R0, #1
+    0x5A83, // x0230 PPBIT: AND R5, R2, R3
+    0x0401, // x0231 BRz PPNEXT
+    0x1004, // x0232 ADD R0, R0, R4
+    0x1904, // x0233 PPNEXT: ADD R4, R4, R4
+    0x16C3, // x0234 ADD R3, R3, R3
+    0x09FA, // x0235 BRn PPBIT
+    0x03F9, // x0236 BRp PPBIT
+    0x1020, // x0237 ADD R0, R0, #0
+    0x0404, // x0238 BRz PPDONE
+    0x4FCA, // x0239 JSR OUT
+    0x1261, // x023A ADD R1, R1, #1
+    0x03EC, // x023B BRp PPWORD
+    0x09EB, // x023C BRn PPWORD
+    0x2018, // x023D PPDONE: LD R0, SAVER0
+    0x2218, // x023E LD R1, SAVER1
+    0x2418, // x023F LD R2, SAVER2
+    0x2618, // x0240 LD R3, SAVER3
+    0x2818, // x0241 LD R4, SAVER4
+    0x2A18, // x0242 LD R5, SAVER5
+    0x2E18, // x0243 LD R7, SAVER7
+    0xC1C0, // x0244 RET
+    0xE032, // x0245 BADTRAP: LEA R0, BADMSG
+    0x4FC3, // x0246 JSR PUTS
+    0x3015, // x0247 HALT: ST R0, HALTR0
+    0xE029, // x0248 LEA R0, HALTMSG
+    0x4FC0, // x0249 JSR PUTS
+    0x2012, // x024A LD R0, HALTR0
+    0x5FE0, // x024B AND R7, R7, #0
+    0xBE04, // x024C STI R7, MCRP
+    0xFE00, // x024D KBSRP: .FILL xFE00
+    0xFE02, // x024E KBDRP: .FILL xFE02
+    0xFE04, // x024F DSRP: .FILL xFE04
+    0xFE06, // x0250 DDRP: .FILL xFE06
+    0xFFFE, // x0251 MCRP: .FILL xFFFE
+    0x00FF, // x0252 LOWBYTE: .FILL x00FF
+    0x0100, // x0253 BIT8: .FILL x0100
+    0x0000, // x0254 OUTR1: .FILL 0
+    0x0000, // x0255 INR7: .FILL 0
+    0x0000, // x0256 SAVER0: .FILL 0
+    0x0000, // x0257 SAVER1: .FILL 0
+    0x0000, // x0258 SAVER2: .FILL 0
+    0x0000, // x0259 SAVER3: .FILL 0
+    0x0000, // x025A SAVER4: .FILL 0
+    0x0000, // x025B SAVER5: .FILL 0
+    0x0000, // x025C SAVER7: .FILL 0
+    0x0000, // x025D HALTR0: .FILL 0
+];
@@ -0,0 +1,82 @@
+//! Sinks for program output beyond a plain writer.
+
+use std::io::{self, Write};
+
+/// Writes everything to both `A` and `B`, e.g. the terminal and a session
+/// log. Install with `VM::set_output`. A failure in either sink fails the
+/// write; bytes may by then have reached the other one.
+pub struct Tee<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: Write, B: Write> Tee<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    /* write_all on both, so neither sink sees a shorter write than the other */
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.first.write_all(buf)?;
+        self.second.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.first.flush()?;
+        self.second.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* a sink that counts its flushes */
+    #[derive(Default)]
+    struct Flushes {
+        bytes: Vec<u8>,
+        flushes: usize,
+    }
+
+    impl Write for Flushes {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.bytes.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn both_sinks_get_identical_bytes() {
+        let mut tee = Tee::new(Vec::new(), Vec::new());
+        tee.write_all(b"Hello, ").unwrap();
+        write!(tee, "World!\n{}", 42).unwrap();
+
+        let (first, second) = tee.into_inner();
+        assert_eq!(first, b"Hello, World!\n42");
+        assert_eq!(second, first);
+    }
+
+    #[test]
+    fn flush_reaches_both_sinks() {
+        let mut tee = Tee::new(Flushes::default(), Flushes::default());
+        tee.write_all(b"x").unwrap();
+        tee.flush().unwrap();
+        tee.flush().unwrap();
+
+        let (first, second) = tee.into_inner();
+        assert_eq!((first.flushes, second.flushes), (2, 2));
+        assert_eq!(second.bytes, b"x");
+    }
+}
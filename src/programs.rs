@@ -0,0 +1,164 @@
+//! Small built-in LC-3 programs, usable as fixtures and as examples
+//! (`lc3 --example hello`). Every program is meant to be loaded at `ORIGIN`.
+
+/// Load address of every program in this module.
+pub const ORIGIN: u16 = crate::vm::DEFAULT_ORIGIN;
+
+/// Names accepted by `example`.
+pub const EXAMPLES: [&str; 2] = ["hello", "fib"];
+
+/// Looks up a program by the name used on the command line.
+pub fn example(name: &str) -> Option<Vec<u16>> {
+    match name {
+        "hello" => Some(hello_world()),
+        "fib" => fib(10),
+        _ => None,
+    }
+}
+
+/// Prints `Hello, World!` followed by a newline, then halts.
+pub fn hello_world() -> Vec<u16> {
+    let mut words = vec![
+        0xE002, // LEA R0, MSG
+        0xF022, // PUTS
+        0xF025, // HALT
+    ];
+    words.extend(stringz("Hello, World!\n"));
+    words
+}
+
+/// Prints the first `n` Fibonacci numbers in decimal, one per line.
+/// The printer works on signed words, so `None` for `n` above 24
+/// (F(23) = 28657 is the last that fits).
+pub fn fib(n: u16) -> Option<Vec<u16>> {
+    if n > 24 {
+        return None;
+    }
+
+    let mut words = FIB.to_vec();
+    words[FIB_N] = n;
+    Some(words)
+}
+
+/* index of the `N` cell holding the count */
+const FIB_N: usize = 0x10;
+
+const FIB: [u16; 68] = [
+    0x5260, // x3000 AND R1, R1, #0
+    0x54A0, // x3001 AND R2, R2, #0
+    0x14A1, // x3002 ADD R2, R2, #1
+    0x260C, // x3003 LD R3, N
+    0x040A, // x3004 BRz DONE
+    0x1060, // x3005 LOOP: ADD R0, R1, #0
+    0x480A, // x3006 JSR PRINT
+    0x5020, // x3007 AND R0, R0, #0
+    0x102A, // x3008 ADD R0, R0, #10
+    0xF021, // x3009 OUT
+    0x1842, // x300A ADD R4, R1, R2
+    0x12A0, // x300B ADD R1, R2, #0
+    0x1520, // x300C ADD R2, R4, #0
+    0x16FF, // x300D ADD R3, R3, #-1
+    0x03F6, // x300E BRp LOOP
+    0xF025, // x300F DONE: HALT
+    0x0000, // x3010 N: .FILL 0
+    0x322D, // x3011 PRINT: ST R1, SAVE1
+    0x342D, // x3012 ST R2, SAVE2
+    0x362D, // x3013 ST R3, SAVE3
+    0x382D, // x3014 ST R4, SAVE4
+    0x3E2D, // x3015 ST R7, SAVE7
+    0x1220, // x3016 ADD R1, R0, #0
+    0xE421, // x3017 LEA R2, POWERS
+    0x56E0, // x3018 AND R3, R3, #0
+    0x6880, // x3019 NEXTP: LDR R4, R2, #0
+    0x0417, // x301A BRz FINISH
+    0x993F, // x301B NOT R4, R4
+    0x1921, // x301C ADD R4, R4, #1
+    0x5020, // x301D AND R0, R0, #0
+    0x1244, // x301E DIGIT: ADD R1, R1, R4
+    0x0802, // x301F BRn GOTD
+    0x1021, // x3020 ADD R0, R0, #1
+    0x03FC, // x3021 BRp DIGIT
+    0x993F, // x3022 GOTD: NOT R4, R4
+    0x1921, // x3023 ADD R4, R4, #1
+    0x1244, // x3024 ADD R1, R1, R4
+    0x1020, // x3025 ADD R0, R0, #0
+    0x0204, // x3026 BRp EMIT
+    0x16E0, // x3027 ADD R3, R3, #0
+    0x0202, // x3028 BRp EMIT
+    0x193F, // x3029 ADD R4, R4, #-1
+    0x0205, // x302A BRp SKIP
+    0x280C, // x302B EMIT: LD R4, ZERO
+    0x1004, // x302C ADD R0, R0, R4
+    0xF021, // x302D OUT
+    0x56E0, // x302E AND R3, R3, #0
+    0x16E1, // x302F ADD R3, R3, #1
+    0x14A1, // x3030 SKIP: ADD R2, R2, #1
+    0x03E7, // x3031 BRp NEXTP
+    0x220C, // x3032 FINISH: LD R1, SAVE1
+    0x240C, // x3033 LD R2, SAVE2
+    0x260C, // x3034 LD R3, SAVE3
+    0x280C, // x3035 LD R4, SAVE4
+    0x2E0C, // x3036 LD R7, SAVE7
+    0xC1C0, // x3037 RET
+    0x0030, // x3038 ZERO: .FILL 48
+    0x2710, // x3039 POWERS: .FILL 10000
+    0x03E8, // x303A .FILL 1000
+    0x0064, // x303B .FILL 100
+    0x000A, // x303C .FILL 10
+    0x0001, // x303D .FILL 1
+    0x0000, // x303E .FILL 0
+    0x0000, // x303F SAVE1: .FILL 0
+    0x0000, // x3040 SAVE2: .FILL 0
+    0x0000, // x3041 SAVE3: .FILL 0
+    0x0000, // x3042 SAVE4: .FILL 0
+    0x0000, // x3043 SAVE7: .FILL 0
+];
+
+/* `.STRINGZ`: one character per word plus a zero terminator */
+pub(crate) fn stringz(s: &str) -> impl Iterator<Item = u16> + '_ {
+    s.bytes().map(u16::from).chain(std::iter::once(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{run_captured, StepResult};
+
+    /* what the program printed before halting, HALT message excluded */
+    fn output_of(words: &[u16]) -> String {
+        let report = run_captured(words, b"", 100_000);
+        assert_eq!(report.result, Ok(StepResult::Halted));
+
+        let text = String::from_utf8(report.output).unwrap();
+        text.strip_suffix("HALT\n").unwrap().to_string()
+    }
+
+    #[test]
+    fn hello_world_prints_its_greeting() {
+        assert_eq!(output_of(&hello_world()), "Hello, World!\n");
+    }
+
+    #[test]
+    fn fib_prints_the_first_n_numbers() {
+        assert_eq!(
+            output_of(&fib(10).unwrap()),
+            "0\n1\n1\n2\n3\n5\n8\n13\n21\n34\n"
+        );
+        assert_eq!(output_of(&fib(0).unwrap()), "");
+    }
+
+    #[test]
+    fn fib_stops_at_the_last_count_that_fits_a_signed_word() {
+        assert!(output_of(&fib(24).unwrap()).ends_with("\n28657\n"));
+        assert_eq!(fib(25), None);
+        assert_eq!(fib(u16::MAX), None);
+    }
+
+    #[test]
+    fn every_example_name_resolves() {
+        for name in EXAMPLES {
+            assert!(example(name).is_some(), "{}", name);
+        }
+        assert_eq!(example("nope"), None);
+    }
+}
@@ -0,0 +1,86 @@
+//! Helpers for testing LC-3 programs from Rust, behind the `testing`
+//! feature.
+
+use crate::vm::{run_captured, StepResult};
+
+/* instructions allowed before a test program is taken to be stuck */
+const CYCLE_LIMIT: u64 = 10_000_000;
+
+/// Runs `words` from `DEFAULT_ORIGIN` with `input` as the keyboard, as
+/// `run_captured` does, and panics unless the program halts having
+/// printed exactly `expected`. The panic message shows both outputs and
+/// the first line where they differ.
+#[track_caller]
+pub fn assert_program_output(words: &[u16], input: &[u8], expected: &str) {
+    let report = run_captured(words, input, CYCLE_LIMIT);
+    let actual = String::from_utf8_lossy(&report.output);
+
+    let ending = match report.result {
+        Ok(StepResult::Halted) => None,
+        Ok(StepResult::LimitReached) => Some(format!(
+            "still running after {} instructions, pc x{:04X}",
+            CYCLE_LIMIT, report.pc
+        )),
+        Ok(other) => Some(format!("stopped with {:?}", other)),
+        Err(err) => Some(format!("failed: {}", err)),
+    };
+
+    if actual == expected && ending.is_none() {
+        return;
+    }
+
+    let mut message = String::from("program output mismatch\n");
+    if let Some(ending) = ending {
+        message.push_str(&format!("program {}\n", ending));
+    }
+
+    let want: Vec<&str> = expected.split('\n').collect();
+    let got: Vec<&str> = actual.split('\n').collect();
+    let differing = (0..want.len().max(got.len())).find(|&line| want.get(line) != got.get(line));
+    if let Some(line) = differing {
+        message.push_str(&format!(
+            "first difference on line {}:\n  expected: {:?}\n    actual: {:?}\n",
+            line + 1,
+            want.get(line).copied().unwrap_or("<end of output>"),
+            got.get(line).copied().unwrap_or("<end of output>")
+        ));
+    }
+
+    message.push_str(&format!(
+        "expected output:\n{:?}\nactual output:\n{:?}",
+        expected, actual
+    ));
+    panic!("{}", message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::programs;
+
+    #[test]
+    fn passes_on_the_expected_output() {
+        assert_program_output(&programs::hello_world(), b"", "Hello, World!\nHALT\n");
+        // GETC; OUT; HALT
+        assert_program_output(&[0xF020, 0xF021, 0xF025], b"q", "qHALT\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "first difference on line 1")]
+    fn panics_on_different_output() {
+        assert_program_output(&programs::hello_world(), b"", "Hello, LC-3!\nHALT\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "program failed: ")]
+    fn panics_when_the_program_faults() {
+        assert_program_output(&[0xD000], b"", "");
+    }
+
+    #[test]
+    #[should_panic(expected = "still running after")]
+    fn panics_when_the_program_never_halts() {
+        // BRnzp #-1
+        assert_program_output(&[0x0FFF], b"", "");
+    }
+}
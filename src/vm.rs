@@ -0,0 +1,2074 @@
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, StdoutLock, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use log::{debug, trace, warn};
+
+use crate::bitset::BitSet;
+use crate::device::MmioDevice;
+use crate::disasm::looks_like_data;
+use crate::error::{LoadError, VmError};
+use crate::input::{Input, LineInput, TerminalInput};
+use crate::instruction::{
+    condition_for, decode, effective_address, pc_relative, to_signed, ConditionFlag, Instruction,
+    OpCode, Operand, Register, TrapCode,
+};
+use crate::os;
+
+pub const MEMORY_MAX: usize = 1 << 16;
+
+/// Where user programs conventionally start, and the PC after `reset`.
+pub const DEFAULT_ORIGIN: u16 = 0x3000;
+/// Default address of the trap vector table (`Options::trap_base`).
+pub const TRAP_VECTOR_BASE: u16 = 0x0000;
+
+/// Keyboard status register: bit 15 is set while a key is waiting.
+pub const KBSR: u16 = 0xFE00;
+/// Keyboard data register: reading it takes the waiting key.
+pub const KBDR: u16 = 0xFE02;
+/// Display status register: bit 15 is set when the display is ready.
+pub const DSR: u16 = 0xFE04;
+/// Display data register: writing it prints the low byte.
+pub const DDR: u16 = 0xFE06;
+/// Non-standard random number register; each read yields a new word.
+pub const RNG: u16 = 0xFE08;
+/// Machine control register: clearing bit 15 stops the clock.
+pub const MCR: u16 = 0xFFFE;
+
+const DEFAULT_RNG_SEED: u64 = 0x4C43_3320_524E_4721;
+
+/* device registers live from here to the top of memory */
+const DEVICE_SPACE_START: u16 = KBSR;
+
+/* everything below this holds the vector tables and the OS */
+const USER_SPACE_START: u16 = DEFAULT_ORIGIN;
+
+/* R0..R7, PC and COND: one slot per `Register` */
+const REGISTER_COUNT: usize = 10;
+
+/// What memory holds before an image is loaded.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum MemInit {
+    /// Every cell starts at zero, as on real hardware after reset.
+    #[default]
+    Zero,
+    /// Every cell starts at the given word; fetching it as an instruction
+    /// is an `IllegalOpcode`, which exposes jumps into unwritten memory.
+    Poison(u16),
+}
+
+/// How `step` treats the reserved opcode (1101) and RTI, which the VM does
+/// not implement.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum ReservedOpPolicy {
+    /// Fail with `VmError::Reserved` or `VmError::UnimplementedRti`.
+    #[default]
+    Error,
+    /// Skip the instruction and carry on.
+    Nop,
+    /// Stop as if the program executed HALT, without printing anything.
+    Halt,
+}
+
+/// Weights for `VM::weighted_cycles`. An instruction costs its opcode's
+/// weight plus `per_access` for each data memory access it makes: one for
+/// LD, ST, LDR and STR, two for LDI and STI. The instruction fetch itself
+/// is not counted as an access.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CostModel {
+    /// Indexed by `OpCode as usize`.
+    pub per_op: [u64; 16],
+    pub per_access: u64,
+}
+
+impl Default for CostModel {
+    /* every instruction costs 1, so weighted_cycles matches cycles */
+    fn default() -> Self {
+        Self {
+            per_op: [1; 16],
+            per_access: 0,
+        }
+    }
+}
+
+/// Construction-time settings. `Options::default()` is what `VM::new()` uses.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Initial contents of every memory cell.
+    pub mem_init: MemInit,
+    /// Start R0..R7 from pseudo-random values generated from this seed,
+    /// on every reset, instead of zero. Flushes out code that relies on
+    /// registers it never set.
+    pub register_seed: Option<u64>,
+    /// Record every address fetched as an instruction; see `executed_addresses`.
+    pub track_coverage: bool,
+    /// Log a warning when a store hits an address already executed as an
+    /// instruction. Records coverage even if `track_coverage` is off.
+    pub warn_self_modifying: bool,
+    /// Fault on program stores below x3000, where the trap/interrupt vector
+    /// tables and OS live. Image loading is not affected.
+    pub protect_system_region: bool,
+    /// Execute TRAP the way the hardware does, jumping through the trap
+    /// vector table in memory, instead of handling the standard traps in Rust.
+    pub vectored_traps: bool,
+    /// Write `\n` as `\r\n` so output doesn't staircase on a terminal in
+    /// raw mode. Leave off when output is piped or captured.
+    pub crlf_output: bool,
+    /// Address of the 256-entry trap vector table.
+    pub trap_base: u16,
+    /// Printed by the IN trap before it waits for a key; `None` prints nothing.
+    pub in_prompt: Option<String>,
+    /// Print a newline after IN echoes the key, as many LC-3 OS images do.
+    pub newline_after_in: bool,
+    /// Words of memory, a power of two no larger than `MEMORY_MAX`. Smaller
+    /// sizes alias: addresses wrap at the boundary. Device registers are
+    /// matched on the full address before wrapping.
+    pub memory_size: usize,
+    /// How many executed instructions `step_back` can undo. Zero turns the
+    /// journal off so plain runs pay nothing for it.
+    pub undo_depth: usize,
+    /// How many of the latest fetched `(pc, instr)` pairs `recent_trace`
+    /// keeps. Zero turns the record off.
+    pub recent_depth: usize,
+    /// Inclusive `(lo, hi)` stack region. When set, LDR and STR with R6 as
+    /// the base fail with `StackOverflow` if they reach outside it.
+    pub stack_bounds: Option<(u16, u16)>,
+    /// Make a KBSR read with no queued key wait for one from the input, so
+    /// polling code (like the OS GETC) sees typed keys. If the input is
+    /// closed KBSR keeps reading not-ready.
+    pub blocking_keyboard: bool,
+    /// What the reserved opcode and the unimplemented RTI do when executed.
+    pub reserved_ops: ReservedOpPolicy,
+    /// Fail with `ExecuteDeviceMemory` when the PC reaches the device
+    /// registers at xFE00 and above, instead of executing their contents.
+    pub guard_device_fetch: bool,
+    /// Have `step` return `NeedsInput` at a GETC or IN with no key queued
+    /// instead of blocking on the input. `run` still reads the input.
+    pub pause_on_input: bool,
+    /// Fail with `JumpToZero` when a branch, jump or call in user code
+    /// (x3000 and up) lands on x0000, usually a target register that was
+    /// never set. Code below x3000 may still jump there.
+    pub catch_jump_to_zero: bool,
+    /// Fail with `PcWrapped` when execution runs sequentially off xFFFF
+    /// onto x0000, which almost always means a missing HALT.
+    pub catch_pc_wrap: bool,
+    /// Most stores (ST, STI, STR and `mem_write` calls) allowed since the
+    /// last reset; the next one fails with `WriteQuotaExceeded`. Loading
+    /// images does not count.
+    pub write_quota: Option<u64>,
+    /// Fail with `SuspectedDataExecution` once this many words in a row
+    /// that `disasm::looks_like_data` flags have been executed: never-taken
+    /// branches (every ASCII character and zero), RTI and the reserved
+    /// opcode. Catches a missing HALT running into `.STRINGZ` data; a
+    /// handful, such as 4, is plenty. `Some(0)` is rejected by
+    /// `VM::with_options`; use `None` to turn the check off.
+    pub data_execution_limit: Option<usize>,
+    /// Entry points to continue at, in order, each time the program halts,
+    /// so several programs loaded side by side run as one batch. Every
+    /// such HALT is recorded in `halt_checkpoints`; once the entries run
+    /// out HALT stops execution as usual.
+    pub continue_past_halt: Vec<u16>,
+    /// Weights behind `weighted_cycles`.
+    pub costs: CostModel,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            mem_init: MemInit::default(),
+            register_seed: None,
+            track_coverage: false,
+            warn_self_modifying: false,
+            protect_system_region: false,
+            vectored_traps: false,
+            crlf_output: false,
+            trap_base: TRAP_VECTOR_BASE,
+            in_prompt: Some(String::from("Enter a character: ")),
+            newline_after_in: false,
+            memory_size: MEMORY_MAX,
+            undo_depth: 0,
+            recent_depth: 16,
+            stack_bounds: None,
+            blocking_keyboard: false,
+            reserved_ops: ReservedOpPolicy::default(),
+            guard_device_fetch: false,
+            pause_on_input: false,
+            catch_jump_to_zero: false,
+            catch_pc_wrap: false,
+            write_quota: None,
+            data_execution_limit: None,
+            continue_past_halt: Vec::new(),
+            costs: CostModel::default(),
+        }
+    }
+}
+
+/// Why `step` (or a run loop built on it) handed control back.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StepResult {
+    /// One instruction executed and the program can keep going.
+    Continue,
+    /// The program executed HALT.
+    Halted,
+    /// Execution stopped with the PC on this breakpoint, before running it.
+    Breakpoint(u16),
+    /// The instruction just executed stored to this watched address.
+    Watchpoint(u16),
+    /// The PC reached the address passed to `run_until_pc`.
+    ReachedTarget,
+    /// The cycle budget ran out before anything else stopped execution.
+    LimitReached,
+    /// With `Options::pause_on_input`, the next instruction is GETC or IN
+    /// and no key is queued. Nothing was executed or read; queue a key
+    /// with `feed_input`/`push_key` and step again.
+    NeedsInput,
+    /// The instruction just executed changed a register watched with
+    /// `add_reg_watch`.
+    RegChanged { reg: Register, old: u16, new: u16 },
+    /// `step_over_trap` ran the TRAP with this vector to completion, and
+    /// it printed `output` bytes.
+    Trapped { vector: u8, output: usize },
+}
+
+/// A contiguous run of memory filled by one image or `load_words` call.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub origin: u16,
+    pub len: usize,
+}
+
+impl Segment {
+    pub fn contains(&self, address: u16) -> bool {
+        (self.origin as usize..self.origin as usize + self.len).contains(&(address as usize))
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = u16> {
+        (self.origin as usize..self.origin as usize + self.len).map(|address| address as u16)
+    }
+}
+
+/// Where `run_step_until_io` left off.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunState {
+    /// The next instruction is GETC or IN and no key is queued. Supply one
+    /// with `feed_input`/`push_key` and call again; nothing was executed
+    /// for that instruction yet.
+    NeedsInput,
+    /// The program executed HALT.
+    Halted,
+    /// Execution stopped on an error.
+    Faulted(VmError),
+    /// From `run_until_output`: the program printed these bytes, which
+    /// went to the caller instead of the output writer.
+    Output(Vec<u8>),
+}
+
+/// An ADD whose operands, read as signed 16-bit values, wrapped.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Overflow {
+    /// Address of the ADD instruction.
+    pub pc: u16,
+    pub lhs: u16,
+    pub rhs: u16,
+    /// The wrapped value that was stored, exactly as without a hook.
+    pub result: u16,
+}
+
+/// What an `on_fault` handler wants done about an illegal instruction.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FaultAction {
+    /// Fail the step with the original error.
+    Abort,
+    /// Carry on from the PC as the handler left it, normally the next word.
+    Skip,
+    /// Execute the word at the faulting address again, e.g. after the
+    /// handler patched it.
+    Retry,
+}
+
+/// What kind of memory access an `Access` was.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AccessKind {
+    /// The instruction fetch at the start of a step.
+    Fetch,
+    Read,
+    Write,
+}
+
+/// One memory access reported to the `on_mem_access` hook.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Access {
+    pub kind: AccessKind,
+    pub address: u16,
+    /// The word read, or the word about to be stored.
+    pub value: u16,
+}
+
+/// Loads `image` into a fresh VM and runs it for at most `max_cycles`
+/// instructions with no keyboard input and output discarded. Meant as a
+/// fuzzing target: it never panics, whatever the bytes. An image that fails
+/// to load has nothing to run and returns `Ok`.
+pub fn run_fuzz(image: &[u8], max_cycles: u64) -> Result<(), VmError> {
+    let mut vm = VM::new();
+    vm.set_input(LineInput::new(io::empty()));
+    vm.set_output(io::sink());
+
+    if vm
+        .load_image_bytes_with_order(image, ByteOrder::BigEndian)
+        .is_err()
+    {
+        return Ok(());
+    }
+    vm.step_n(max_cycles).map(|_| ())
+}
+
+/// Everything observable about one `run_captured` call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunReport {
+    /// `(address, word)` of every instruction fetched, in execution order.
+    pub trace: Vec<(u16, u16)>,
+    /// All bytes the program printed.
+    pub output: Vec<u8>,
+    /// R0..R7 when the run ended.
+    pub registers: [u16; 8],
+    pub pc: u16,
+    pub cond: u16,
+    /// Instructions executed.
+    pub cycles: u64,
+    /// `Halted`, `LimitReached` after `limit` instructions, or the error.
+    pub result: Result<StepResult, VmError>,
+}
+
+/// Loads `words` at `DEFAULT_ORIGIN` into a fresh VM, feeds it `input` as
+/// the keyboard and runs at most `limit` instructions, capturing the lot.
+/// Meant for golden-file tests of LC-3 programs.
+pub fn run_captured(words: &[u16], input: &[u8], limit: u64) -> RunReport {
+    let output = SharedBuffer::default();
+
+    let mut vm = VM::new();
+    vm.set_input(LineInput::new(io::Cursor::new(input.to_vec())));
+    vm.set_output(output.clone());
+    vm.load_words(DEFAULT_ORIGIN, words);
+
+    let mut trace = Vec::new();
+    let mut result = Ok(StepResult::LimitReached);
+    for _ in 0..limit {
+        let pc = vm.pc();
+        trace.push((pc, vm.peek(pc)));
+
+        match vm.step() {
+            Ok(StepResult::Halted) => {
+                result = Ok(StepResult::Halted);
+                break;
+            }
+            Ok(_) => {}
+            Err(err) => {
+                result = Err(err);
+                break;
+            }
+        }
+    }
+
+    let mut registers = [0; 8];
+    registers.copy_from_slice(&vm.registers[..8]);
+
+    RunReport {
+        trace,
+        output: output.0.take(),
+        registers,
+        pc: vm.pc(),
+        cond: vm.cond(),
+        cycles: vm.cycles(),
+        result,
+    }
+}
+
+/* `x3000`, `0x3000` or bare `3000`, at most four digits */
+fn parse_hex_word(token: &str) -> Option<u16> {
+    let digits = token
+        .strip_prefix("0x")
+        .or_else(|| token.strip_prefix(['x', 'X']))
+        .unwrap_or(token);
+
+    if digits.is_empty() || digits.len() > 4 {
+        return None;
+    }
+    u16::from_str_radix(digits, 16).ok()
+}
+
+/* an output sink the caller can still read after handing a clone to the VM */
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// How the words of an object image are laid out as bytes.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// The standard LC-3 layout.
+    #[default]
+    BigEndian,
+    LittleEndian,
+}
+
+/* splitmix64: fine statistically, and any seed (even 0) works */
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/* the origin and payload words of an object image stored in `order` */
+fn parse_image(bytes: &[u8], order: ByteOrder) -> Result<(u16, Vec<u16>), LoadError> {
+    if bytes.len() < 2 {
+        return Err(LoadError::BadFormat("image is missing its origin word"));
+    }
+    if !bytes.len().is_multiple_of(2) {
+        return Err(LoadError::BadFormat("image ends in half a word"));
+    }
+
+    let word = |chunk: &[u8]| match order {
+        ByteOrder::BigEndian => u16::from_be_bytes([chunk[0], chunk[1]]),
+        ByteOrder::LittleEndian => u16::from_le_bytes([chunk[0], chunk[1]]),
+    };
+
+    let origin = word(&bytes[..2]);
+    let words = bytes[2..].chunks_exact(2).map(word).collect();
+    Ok((origin, words))
+}
+
+/* an image file as read from disk: decompressed first if it is gzip and
+the `gzip` feature is on, then parsed like `read_image` does */
+pub(crate) fn parse_image_file(bytes: Vec<u8>) -> Result<(u16, Vec<u16>), LoadError> {
+    /* gzip: magic 1F 8B, then 08 for deflate */
+    #[cfg(feature = "gzip")]
+    let bytes = if bytes.starts_with(&[0x1F, 0x8B, 0x08]) {
+        let mut image = Vec::new();
+        flate2::read::GzDecoder::new(&bytes[..]).read_to_end(&mut image)?;
+        image
+    } else {
+        bytes
+    };
+
+    parse_image(&bytes, ByteOrder::default())
+}
+
+/// Registers and memory as taken by `VM::snapshot`, and whether the
+/// machine had just halted. Equal snapshots are equal machine states, so
+/// a `HashSet<VmSnapshot>` can spot a program revisiting a state. The
+/// derived `Hash` feeds memory to the hasher as one slice rather than
+/// word by word.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VmSnapshot {
+    registers: [u16; REGISTER_COUNT],
+    memory: Box<[u16]>,
+    halted: bool,
+}
+
+/* leads a snapshot file, ahead of the registers and then memory */
+const SNAPSHOT_MAGIC: &[u8; 4] = b"LC3S";
+/* the same, for a machine that had just halted */
+const HALTED_SNAPSHOT_MAGIC: &[u8; 4] = b"LC3H";
+
+impl VmSnapshot {
+    /// Words of memory captured, for `Options::memory_size` when restoring.
+    pub fn memory_size(&self) -> usize {
+        self.memory.len()
+    }
+
+    /// Whether the snapshot was taken right after the program halted, so
+    /// that there is nothing left to resume: the PC is past the HALT.
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Saves the snapshot to a file; see `write_to` for the format.
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    /// Writes `LC3S` (`LC3H` if halted), then R0..R7, PC and COND, then
+    /// every memory word, all big-endian like an object image.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(if self.halted {
+            HALTED_SNAPSHOT_MAGIC
+        } else {
+            SNAPSHOT_MAGIC
+        })?;
+        for word in self.registers.iter().chain(self.memory.iter()) {
+            writer.write_all(&word.to_be_bytes())?;
+        }
+
+        writer.flush()
+    }
+
+    /// Reads a file saved by `write`.
+    pub fn read(path: &str) -> Result<Self, LoadError> {
+        Self::read_from(File::open(path)?)
+    }
+
+    /// Same as `read`, but from any byte source.
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self, LoadError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        let halted = bytes.starts_with(HALTED_SNAPSHOT_MAGIC);
+        let body = bytes
+            .strip_prefix(SNAPSHOT_MAGIC)
+            .or_else(|| bytes.strip_prefix(HALTED_SNAPSHOT_MAGIC))
+            .ok_or(LoadError::BadFormat("not a snapshot file"))?;
+        if !body.len().is_multiple_of(2) {
+            return Err(LoadError::BadFormat("snapshot has an odd number of bytes"));
+        }
+        let mut words = body
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+
+        let mut registers = [0; REGISTER_COUNT];
+        for register in &mut registers {
+            *register = words
+                .next()
+                .ok_or(LoadError::BadFormat("snapshot is truncated"))?;
+        }
+        let memory: Box<[u16]> = words.collect();
+
+        if !memory.len().is_power_of_two() || memory.len() > MEMORY_MAX {
+            return Err(LoadError::BadFormat("snapshot memory has an unusable size"));
+        }
+
+        Ok(Self {
+            registers,
+            memory,
+            halted,
+        })
+    }
+}
+
+/* what one instruction changed, enough to put it back */
+struct Undo {
+    registers: [u16; REGISTER_COUNT],
+    /* (cell, previous value) in store order */
+    stores: Vec<(usize, u16)>,
+    call_depth: usize,
+    innermost_call: Option<u16>,
+}
+
+type OverflowHook = Box<dyn FnMut(&Overflow)>;
+type AccessHook = Box<dyn FnMut(&Access)>;
+type FaultHandler = Box<dyn FnMut(&mut VM, u16) -> FaultAction>;
+type TrapHook = Box<dyn FnMut([u16; 3]) -> u16>;
+type BreakCondition = Box<dyn Fn(&VM) -> bool>;
+
+pub struct VM {
+    memory: Box<[u16]>,
+    registers: [u16; REGISTER_COUNT],
+    options: Options,
+    coverage: BitSet,
+    opcode_counts: [u64; 16],
+    cycles: u64,
+    weighted_cycles: u64,
+    /* bytes the program has printed, for step_over_trap */
+    output_written: u64,
+    writes: u64,
+    /* (address of the HALT, R0) each time while continue_past_halt is in use */
+    halts: Vec<(u16, u16)>,
+    /* the last step executed a HALT that ended the run */
+    halted: bool,
+    /* consecutive data-looking words executed, for data_execution_limit */
+    data_run: usize,
+    breakpoints: BTreeSet<u16>,
+    /* breakpoints in here only stop when their predicate holds */
+    break_conditions: HashMap<u16, BreakCondition>,
+    watchpoints: BTreeSet<u16>,
+    reg_watches: Vec<Register>,
+    /* watched address stored to by the current instruction */
+    watch_hit: Option<u16>,
+    segments: Vec<Segment>,
+    call_stack: Vec<u16>,
+    /* set while run_until_output collects what the program prints */
+    captured: Option<Vec<u8>>,
+    pending_state: Option<RunState>,
+    journal: VecDeque<Undo>,
+    recent: VecDeque<(u16, u16)>,
+    /* the entry for the instruction being executed, while journaling */
+    recording: Option<Undo>,
+    pending_keys: VecDeque<u8>,
+    overflow_hook: Option<OverflowHook>,
+    access_hook: Option<AccessHook>,
+    fault_handler: Option<FaultHandler>,
+    trap_hooks: HashMap<u8, TrapHook>,
+    rng_state: u64,
+    devices: HashMap<u16, Box<dyn MmioDevice>>,
+    /* set by a store clearing the MCR clock bit, ends the current step */
+    clock_stopped: bool,
+    input: Box<dyn Input>,
+    output: Box<dyn Write>,
+    /* output is still the default stdout, so runs lock it */
+    output_is_stdout: bool,
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VM {
+    pub fn new() -> Self {
+        Self::with_options(Options::default())
+    }
+
+    /// A default VM with `words` loaded at `origin` and the PC there,
+    /// ready to `run`: for programs that live in the embedding code rather
+    /// than in a file.
+    pub fn from_words(origin: u16, words: &[u16]) -> Self {
+        let mut vm = Self::new();
+        vm.load_words(origin, words);
+        vm.set_pc(origin);
+        vm
+    }
+
+    pub fn with_options(options: Options) -> Self {
+        let fill = match options.mem_init {
+            MemInit::Zero => 0,
+            MemInit::Poison(word) => word,
+        };
+
+        assert!(
+            options.memory_size.is_power_of_two() && options.memory_size <= MEMORY_MAX,
+            "memory_size must be a power of two up to {}",
+            MEMORY_MAX
+        );
+        assert!(
+            options.data_execution_limit != Some(0),
+            "data_execution_limit must be at least 1"
+        );
+
+        let mut vm = Self {
+            memory: vec![fill; options.memory_size].into_boxed_slice(),
+            registers: [0; REGISTER_COUNT],
+            options,
+            coverage: BitSet::new(),
+            opcode_counts: [0; 16],
+            cycles: 0,
+            weighted_cycles: 0,
+            output_written: 0,
+            writes: 0,
+            halts: Vec::new(),
+            halted: false,
+            data_run: 0,
+            breakpoints: BTreeSet::new(),
+            break_conditions: HashMap::new(),
+            watchpoints: BTreeSet::new(),
+            reg_watches: Vec::new(),
+            watch_hit: None,
+            segments: Vec::new(),
+            call_stack: Vec::new(),
+            captured: None,
+            pending_state: None,
+            journal: VecDeque::new(),
+            recent: VecDeque::new(),
+            recording: None,
+            pending_keys: VecDeque::new(),
+            overflow_hook: None,
+            access_hook: None,
+            fault_handler: None,
+            trap_hooks: HashMap::new(),
+            rng_state: DEFAULT_RNG_SEED,
+            devices: HashMap::new(),
+            clock_stopped: false,
+            input: Box::new(TerminalInput::default()),
+            /* not locked here: a lock held for the VM's lifetime would block
+             * every other thread's stdout. Each run holds it instead, see
+             * lock_stdout */
+            output: Box::new(io::stdout()),
+            output_is_stdout: true,
+        };
+        vm.reset();
+        vm
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn options_mut(&mut self) -> &mut Options {
+        &mut self.options
+    }
+
+    /// Addresses executed as instructions while `track_coverage` was on.
+    pub fn executed_addresses(&self) -> &BitSet {
+        &self.coverage
+    }
+
+    /// How many times each opcode executed, indexed by `OpCode as usize`.
+    pub fn opcode_histogram(&self) -> [u64; 16] {
+        self.opcode_counts
+    }
+
+    /// Instructions executed since the last reset.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Cost of the instructions executed since the last reset, weighted by
+    /// `Options::costs`.
+    pub fn weighted_cycles(&self) -> u64 {
+        self.weighted_cycles
+    }
+
+    /// Puts registers back in their power-on state and clears run
+    /// statistics, keeping memory (and so any loaded image) intact so the
+    /// program can be run again.
+    pub fn reset(&mut self) {
+        self.registers = [0; REGISTER_COUNT];
+        if let Some(seed) = self.options.register_seed {
+            let mut state = seed;
+            for register in &mut self.registers[..8] {
+                *register = splitmix64(&mut state) as u16;
+            }
+        }
+        // since exacly one condition flag should be set at any given time, set the Z flag
+        self.set_register(Register::Cond, ConditionFlag::Zro as u16);
+        // set the PC to starting position 0x3000 is the default
+        self.set_register(Register::Pc, DEFAULT_ORIGIN);
+
+        self.coverage.clear();
+        self.opcode_counts = [0; 16];
+        self.cycles = 0;
+        self.weighted_cycles = 0;
+        self.writes = 0;
+        self.data_run = 0;
+        self.halts.clear();
+        self.halted = false;
+        self.call_stack.clear();
+        self.journal.clear();
+        self.recent.clear();
+        self.pending_state = None;
+    }
+
+    /// The last `Options::recent_depth` fetched `(pc, instr)` pairs, oldest
+    /// first. After an error the final entry is the instruction that failed.
+    pub fn recent_trace(&self) -> Vec<(u16, u16)> {
+        self.recent.iter().copied().collect()
+    }
+
+    /// Copies registers and memory, noting whether the last step halted.
+    /// Breakpoints, devices, statistics and pending input are not part of
+    /// it.
+    pub fn snapshot(&self) -> VmSnapshot {
+        VmSnapshot {
+            registers: self.registers,
+            memory: self.memory.clone(),
+            halted: self.halted,
+        }
+    }
+
+    /// Puts back the registers and memory of `snapshot`, which must come
+    /// from a VM with the same `memory_size`. The undo journal and any halt
+    /// or fault `run_until_output` still has to report are dropped, since
+    /// they no longer describe how this state was reached.
+    pub fn restore(&mut self, snapshot: &VmSnapshot) {
+        self.registers = snapshot.registers;
+        self.memory.copy_from_slice(&snapshot.memory);
+        self.halted = snapshot.halted;
+        self.journal.clear();
+        self.pending_state = None;
+    }
+
+    /// Runs from the current PC until the program halts. By convention a
+    /// program leaves its exit status in R0, which is returned.
+    pub fn run(&mut self) -> Result<u16, VmError> {
+        let _stdout = self.lock_stdout();
+        loop {
+            match self.step()? {
+                StepResult::Halted => return Ok(self.get_register(Register::R0)),
+                StepResult::NeedsInput => self.wait_for_key()?,
+                _ => {}
+            }
+        }
+    }
+
+    /// Runs like `run`, but gives up with `CycleLimitExceeded` unless the
+    /// program halts within `max_cycles` instructions. Returns how many it
+    /// took, HALT included: the one-call "does this terminate?" test.
+    pub fn expect_halt(&mut self, max_cycles: u64) -> Result<u64, VmError> {
+        let _stdout = self.lock_stdout();
+        let start = self.cycles;
+
+        while self.cycles - start < max_cycles {
+            match self.step()? {
+                StepResult::Halted => return Ok(self.cycles - start),
+                StepResult::NeedsInput => self.wait_for_key()?,
+                _ => {}
+            }
+        }
+        Err(VmError::CycleLimitExceeded(max_cycles))
+    }
+
+    /* lets the fault handler, if any, decide whether `error` ends the step */
+    fn handle_fault(&mut self, pc: u16, instr: u16, error: VmError) -> Result<StepResult, VmError> {
+        let Some(mut handler) = self.fault_handler.take() else {
+            return Err(error);
+        };
+        let action = handler(self, instr);
+        /* unless the handler installed a replacement for itself */
+        if self.fault_handler.is_none() {
+            self.fault_handler = Some(handler);
+        }
+
+        match action {
+            FaultAction::Abort => Err(error),
+            FaultAction::Skip => Ok(StepResult::Continue),
+            FaultAction::Retry => {
+                self.set_pc(pc);
+                Ok(StepResult::Continue)
+            }
+        }
+    }
+
+    /* blocks on the input for the key a paused GETC/IN is waiting for */
+    fn wait_for_key(&mut self) -> Result<(), VmError> {
+        let key = self.input.getchar().ok_or(VmError::InputClosed)?;
+        self.pending_keys.push_back(key);
+        Ok(())
+    }
+
+    /// Replaces the text the IN trap prints before reading a key.
+    pub fn set_in_prompt(&mut self, prompt: Option<String>) {
+        self.options.in_prompt = prompt;
+    }
+
+    /// Like `run`, but also returns the wall-clock time spent and the number
+    /// of instructions executed, for measuring interpreter throughput.
+    pub fn run_timed(&mut self) -> (Result<(), VmError>, Duration, u64) {
+        let start_cycles = self.cycles;
+        let start = Instant::now();
+        let result = self.run().map(|_| ());
+        let elapsed = start.elapsed();
+        (result, elapsed, self.cycles - start_cycles)
+    }
+
+    /// Executes the instruction at PC and reports whether the VM can carry on.
+    pub fn step(&mut self) -> Result<StepResult, VmError> {
+        self.halted = false;
+        let address = self.pc();
+        let result = self.step_once()?;
+        if result != StepResult::Halted || self.options.continue_past_halt.is_empty() {
+            self.halted = result == StepResult::Halted;
+            return Ok(result);
+        }
+
+        /* a checkpoint: note it, then move on to the next program if any */
+        self.halts.push((address, self.get_register(Register::R0)));
+        match self.options.continue_past_halt.get(self.halts.len() - 1) {
+            Some(&entry) => {
+                self.set_pc(entry);
+                Ok(StepResult::Continue)
+            }
+            None => {
+                self.halted = true;
+                Ok(result)
+            }
+        }
+    }
+
+    /// `(address, R0)` for every HALT since the last reset while
+    /// `Options::continue_past_halt` was set, the final one included. The
+    /// address is the halting instruction's own, not the PC after it.
+    pub fn halt_checkpoints(&self) -> &[(u16, u16)] {
+        &self.halts
+    }
+
+    fn step_once(&mut self) -> Result<StepResult, VmError> {
+        if self.options.pause_on_input && self.waiting_for_input() {
+            return Ok(StepResult::NeedsInput);
+        }
+
+        if self.options.undo_depth > 0 {
+            self.recording = Some(Undo {
+                registers: self.registers,
+                stores: Vec::new(),
+                call_depth: self.call_stack.len(),
+                innermost_call: self.call_stack.last().copied(),
+            });
+        }
+        let before = self.registers;
+        let result = self.execute();
+
+        if let Some(undo) = self.recording.take() {
+            if self.journal.len() >= self.options.undo_depth {
+                self.journal.pop_front();
+            }
+            self.journal.push_back(undo);
+        }
+
+        if std::mem::take(&mut self.clock_stopped) {
+            debug!("clock stopped at x{:04X}", self.pc());
+            return result.map(|_| StepResult::Halted);
+        }
+
+        if result == Ok(StepResult::Continue) {
+            let changed = self.reg_watches.iter().find_map(|&reg| {
+                let (old, new) = (before[reg as usize], self.get_register(reg));
+                (old != new).then_some(StepResult::RegChanged { reg, old, new })
+            });
+            if let Some(changed) = changed {
+                return Ok(changed);
+            }
+        }
+        result
+    }
+
+    /// Reverts the most recent instruction recorded by the journal (see
+    /// `Options::undo_depth`), restoring registers, memory and the call
+    /// stack. Output already written and keys already read stay consumed.
+    /// Returns false when there is nothing left to undo.
+    pub fn step_back(&mut self) -> bool {
+        let Some(undo) = self.journal.pop_back() else {
+            return false;
+        };
+
+        for &(cell, value) in undo.stores.iter().rev() {
+            self.memory[cell] = value;
+        }
+        self.registers = undo.registers;
+
+        self.call_stack.truncate(undo.call_depth);
+        if self.call_stack.len() < undo.call_depth {
+            self.call_stack.extend(undo.innermost_call);
+        }
+        true
+    }
+
+    fn execute(&mut self) -> Result<StepResult, VmError> {
+        self.watch_hit = None;
+
+        /* mem red and advance pc */
+        let pc = self.get_register(Register::Pc);
+        if self.options.guard_device_fetch && pc >= DEVICE_SPACE_START {
+            return Err(VmError::ExecuteDeviceMemory(pc));
+        }
+        let instr: u16 = self.read_memory(pc);
+        self.notify_access(AccessKind::Fetch, pc, instr);
+        self.set_register(Register::Pc, pc.wrapping_add(1));
+
+        if self.options.recent_depth > 0 {
+            if self.recent.len() >= self.options.recent_depth {
+                self.recent.pop_front();
+            }
+            self.recent.push_back((pc, instr));
+        }
+
+        if self.options.track_coverage || self.options.warn_self_modifying {
+            self.coverage.insert(pc);
+        }
+
+        if let Some(limit) = self.options.data_execution_limit {
+            self.data_run = if looks_like_data(instr) {
+                self.data_run + 1
+            } else {
+                0
+            };
+            /* a limit of 0 set through options_mut acts as 1 */
+            if self.data_run > 0 && self.data_run >= limit {
+                /* the run started this many words back */
+                let start = pc.wrapping_sub(self.data_run as u16 - 1);
+                return Err(VmError::SuspectedDataExecution { pc: start });
+            }
+        }
+
+        if self.options.mem_init == MemInit::Poison(instr) {
+            warn!("poisoned word x{:04X} executed at x{:04X}", instr, pc);
+            return self.handle_fault(pc, instr, VmError::IllegalOpcode { pc, instr });
+        }
+
+        let instruction = decode(instr);
+        self.opcode_counts[instruction.opcode() as usize] += 1;
+        self.cycles += 1;
+
+        let accesses = match instruction.opcode() {
+            OpCode::Ld | OpCode::St | OpCode::Ldr | OpCode::Str => 1,
+            OpCode::Ldi | OpCode::Sti => 2,
+            _ => 0,
+        };
+        let costs = &self.options.costs;
+        self.weighted_cycles +=
+            costs.per_op[instruction.opcode() as usize] + costs.per_access * accesses;
+
+        match instruction {
+            Instruction::Add { dr, sr1, operand } => {
+                let lhs = self.get_register(sr1);
+                let rhs = match operand {
+                    Operand::Imm(imm5) => imm5,
+                    Operand::Reg(sr2) => self.get_register(sr2),
+                };
+                let result = lhs.wrapping_add(rhs);
+
+                if let Some(hook) = &mut self.overflow_hook {
+                    if to_signed(lhs).checked_add(to_signed(rhs)).is_none() {
+                        hook(&Overflow {
+                            pc,
+                            lhs,
+                            rhs,
+                            result,
+                        });
+                    }
+                }
+
+                self.set_register(dr, result);
+                self.update_flags_with(result);
+            }
+            Instruction::And { dr, sr1, operand } => {
+                let result = match operand {
+                    Operand::Imm(imm5) => self.get_register(sr1) & imm5,
+                    Operand::Reg(sr2) => self.get_register(sr1) & self.get_register(sr2),
+                };
+
+                self.set_register(dr, result);
+                self.update_flags_with(result);
+            }
+            Instruction::Not { dr, sr } => {
+                let result = !self.get_register(sr);
+                self.set_register(dr, result);
+                self.update_flags_with(result);
+            }
+            Instruction::Br { cond, offset } => {
+                /* taken if any selected flag is set: nzp = 000 never branches, 111 always */
+                if self.get_register(Register::Cond) & cond != 0 {
+                    self.set_register(Register::Pc, pc_relative(pc, offset));
+                }
+            }
+            Instruction::Jmp { base } => {
+                let target_address = self.get_register(base);
+                self.set_register(Register::Pc, target_address);
+
+                // RET returns from the innermost call
+                if base == Register::R7 {
+                    self.call_stack.pop();
+                }
+            }
+            Instruction::Jsr { offset } => {
+                /* first save incremented Pc into R7 */
+                let link = self.get_register(Register::Pc);
+                self.set_register(Register::R7, link);
+                self.call_stack.push(link);
+
+                // JSR: PC-relative offset
+                self.set_register(Register::Pc, pc_relative(pc, offset));
+            }
+            Instruction::Jsrr { base } => {
+                /* read the target first: for JSRR R7 it must be the old R7, not the link */
+                let target_address = self.get_register(base);
+
+                /* then save incremented Pc into R7 */
+                let pc = self.get_register(Register::Pc);
+                self.set_register(Register::R7, pc);
+                self.call_stack.push(pc);
+
+                // JSRR: Base register
+                self.set_register(Register::Pc, target_address);
+            }
+            Instruction::Ld { dr, offset } => {
+                let value = self.mem_read(pc_relative(pc, offset));
+                self.set_register(dr, value);
+                self.update_flags_with(value);
+            }
+            Instruction::Ldi { dr, offset } => {
+                /* add pc_offset to the current PC, look at that memory location to get the final address */
+                let addr = self.mem_read(pc_relative(pc, offset));
+                // Read the actual value from that address
+                let val = self.mem_read(addr);
+
+                self.set_register(dr, val);
+                self.update_flags_with(val);
+            }
+            Instruction::Ldr { dr, base, offset } => {
+                /* Add offse to content of baser register */
+                let address = effective_address(self.get_register(base), offset);
+                self.check_stack(pc, base, address)?;
+
+                /* Get the content in memory of address */
+                let value = self.mem_read(address);
+
+                /*Load vlaue into DR*/
+                self.set_register(dr, value);
+
+                /* Update flags with the content */
+                self.update_flags_with(value);
+            }
+            Instruction::Lea { dr, offset } => {
+                /* Incremented PC plus the offset */
+                let address = pc_relative(pc, offset);
+
+                /*This address is loaded into DR*/
+                self.set_register(dr, address);
+
+                /*The conditions are set based on the value loaded */
+                self.update_flags_with(address);
+            }
+            Instruction::St { sr, offset } => {
+                /*Content of the register SR*/
+                let value = self.get_register(sr);
+
+                /* Memory Address */
+                let address = pc_relative(pc, offset);
+
+                self.mem_write(address, value)?;
+            }
+            Instruction::Sti { sr, offset } => {
+                /*Content of the register SR*/
+                let value = self.get_register(sr);
+
+                /* Address of the pointer cell */
+                let pointer = pc_relative(pc, offset);
+
+                /* Both the pointer read and the final store go through the normal memory
+                 * path, so a pointer resolving to a device register (e.g. DDR) drives
+                 * that device exactly like a direct ST/STR to it would */
+                let address = self.mem_read(pointer);
+                self.mem_write(address, value)?;
+            }
+            Instruction::Str { sr, base, offset } => {
+                /* memory address*/
+                let address = effective_address(self.get_register(base), offset);
+                self.check_stack(pc, base, address)?;
+
+                self.mem_write(address, self.get_register(sr))?;
+            }
+            Instruction::Trap { vector } => {
+                self.set_register(Register::R7, self.get_register(Register::Pc));
+                trace!("trap x{:02X} at x{:04X}", vector, pc);
+
+                if TrapCode::try_from(vector as u16).is_err() {
+                    if let Some(hook) = self.trap_hooks.get_mut(&vector) {
+                        let args = [
+                            self.registers[Register::R0 as usize],
+                            self.registers[Register::R1 as usize],
+                            self.registers[Register::R2 as usize],
+                        ];
+                        let value = hook(args);
+                        self.set_register(Register::R0, value);
+                        self.update_flags(Register::R0);
+                        return Ok(StepResult::Continue);
+                    }
+                }
+
+                if self.options.vectored_traps {
+                    let entry = self.options.trap_base.wrapping_add(vector as u16);
+                    let routine = self.mem_read(entry);
+                    self.call_stack.push(self.get_register(Register::Pc));
+                    self.set_register(Register::Pc, routine);
+                    return Ok(StepResult::Continue);
+                }
+
+                let Ok(trap) = TrapCode::try_from(vector as u16) else {
+                    warn!("unknown trap x{:02X} at x{:04X}", vector, pc);
+                    return Err(VmError::UnknownTrap { pc, vector });
+                };
+                match trap {
+                    TrapCode::Getc => {
+                        let ch = self.read_key()?;
+                        self.set_register(Register::R0, ch);
+                        self.update_flags(Register::R0);
+                    }
+                    TrapCode::Out => {
+                        let ch = self.get_register(Register::R0) as u8;
+                        self.write_output(&[ch])?;
+                    }
+                    TrapCode::Puts => {
+                        let mut address = self.get_register(Register::R0);
+                        let mut text = Vec::new();
+                        /* a missing terminator stops after one full pass over memory */
+                        for _ in 0..MEMORY_MAX {
+                            let ch = self.mem_read(address);
+
+                            if ch == 0 {
+                                break;
+                            }
+
+                            text.push(ch as u8);
+                            address = address.wrapping_add(1)
+                        }
+
+                        self.write_output(&text)?;
+                    }
+                    TrapCode::In => {
+                        if let Some(prompt) = self.options.in_prompt.clone() {
+                            self.write_output(prompt.as_bytes())?; // Make sure prompt appears before input
+                        }
+
+                        let ch = self.read_key()?; // Read unbuffered character
+                        self.write_output(&[ch as u8])?; // Echo back
+                        if self.options.newline_after_in {
+                            self.write_output(b"\n")?;
+                        }
+
+                        self.set_register(Register::R0, ch);
+                        self.update_flags(Register::R0);
+                    }
+                    TrapCode::Putsp => {
+                        /*one char per byte (two bytes per word) here we need to swap back to
+                         * big endian format*/
+                        let mut address = self.get_register(Register::R0);
+                        let mut text = Vec::new();
+
+                        /* the first zero byte ends the string, even the high byte of an
+                         * odd-length string's last word */
+                        'words: for _ in 0..MEMORY_MAX {
+                            let word = self.mem_read(address);
+
+                            for char in [(word & 0xFF) as u8, (word >> 8) as u8] {
+                                if char == 0 {
+                                    break 'words;
+                                }
+                                text.push(char);
+                            }
+                            address = address.wrapping_add(1);
+                        }
+
+                        self.write_output(&text)?;
+                    }
+                    TrapCode::Halt => {
+                        debug!("halt at x{:04X}", pc);
+                        self.write_output(b"HALT\n")?;
+                        return Ok(StepResult::Halted);
+                    }
+                }
+            }
+            Instruction::Res | Instruction::Rti => match self.options.reserved_ops {
+                ReservedOpPolicy::Error if instruction == Instruction::Res => {
+                    warn!("reserved opcode x{:04X} executed at x{:04X}", instr, pc);
+                    return self.handle_fault(pc, instr, VmError::Reserved { pc, instr });
+                }
+                ReservedOpPolicy::Error => {
+                    warn!("unimplemented RTI executed at x{:04X}", pc);
+                    return self.handle_fault(pc, instr, VmError::UnimplementedRti { pc });
+                }
+                ReservedOpPolicy::Nop => {}
+                ReservedOpPolicy::Halt => return Ok(StepResult::Halted),
+            },
+        }
+
+        let transfers_control = matches!(
+            instruction,
+            Instruction::Br { .. }
+                | Instruction::Jmp { .. }
+                | Instruction::Jsr { .. }
+                | Instruction::Jsrr { .. }
+        );
+        if self.options.catch_jump_to_zero
+            && transfers_control
+            && pc >= USER_SPACE_START
+            && self.pc() == 0
+        {
+            return Err(VmError::JumpToZero { pc });
+        }
+        /* an untaken branch, NOP included, falls through like any other word */
+        let jumped = match instruction {
+            Instruction::Br { cond, .. } => self.get_register(Register::Cond) & cond != 0,
+            Instruction::Jmp { .. }
+            | Instruction::Jsr { .. }
+            | Instruction::Jsrr { .. }
+            | Instruction::Trap { .. } => true,
+            _ => false,
+        };
+        if self.options.catch_pc_wrap && pc == 0xFFFF && self.pc() == 0 && !jumped {
+            return Err(VmError::PcWrapped);
+        }
+
+        Ok(StepResult::Continue)
+    }
+
+    /// Runs until the program halts or the PC lands on a breakpoint. The
+    /// instruction at the starting PC always executes, so calling this
+    /// again after a breakpoint hit moves past it.
+    pub fn run_until_break(&mut self) -> Result<StepResult, VmError> {
+        let _stdout = self.lock_stdout();
+        loop {
+            if let Some(stop) = self.step_checked(None)? {
+                return Ok(stop);
+            }
+        }
+    }
+
+    /// Run to cursor: like `run_until_break`, but also stops with
+    /// `ReachedTarget` when the PC arrives at `target` (before executing
+    /// it), and with `LimitReached` after `max_cycles` instructions.
+    pub fn run_until_pc(&mut self, target: u16, max_cycles: u64) -> Result<StepResult, VmError> {
+        let _stdout = self.lock_stdout();
+        for _ in 0..max_cycles {
+            if let Some(stop) = self.step_checked(Some(target))? {
+                return Ok(stop);
+            }
+        }
+
+        Ok(StepResult::LimitReached)
+    }
+
+    /// Step out: runs until the subroutine the PC is in returns, stopping
+    /// with `ReachedTarget` once `call_stack` is one frame shallower. A
+    /// breakpoint, watchpoint or HALT stops it first. Outside any call
+    /// this is just `run_until_break`.
+    pub fn step_out(&mut self) -> Result<StepResult, VmError> {
+        let _stdout = self.lock_stdout();
+        let depth = self.call_stack.len();
+
+        loop {
+            if let Some(stop) = self.step_checked(None)? {
+                return Ok(stop);
+            }
+            if self.call_stack.len() < depth {
+                return Ok(StepResult::ReachedTarget);
+            }
+        }
+    }
+
+    /// Executes up to `n` instructions, stopping early on HALT, a
+    /// breakpoint or an error. `Continue` means all `n` ran.
+    pub fn step_n(&mut self, n: u64) -> Result<StepResult, VmError> {
+        let _stdout = self.lock_stdout();
+        for _ in 0..n {
+            if let Some(stop) = self.step_checked(None)? {
+                return Ok(stop);
+            }
+        }
+
+        Ok(StepResult::Continue)
+    }
+
+    /* while the output is stdout, a run keeps it locked: no other thread's
+    output lands in the middle of the program's, and each trap's write
+    re-enters the lock this thread holds instead of contending for it */
+    fn lock_stdout(&self) -> Option<StdoutLock<'static>> {
+        self.output_is_stdout.then(|| io::stdout().lock())
+    }
+
+    /* R6 is the stack pointer by convention; other bases are never checked */
+    fn check_stack(&self, pc: u16, base: Register, address: u16) -> Result<(), VmError> {
+        match self.options.stack_bounds {
+            Some((lo, hi)) if base == Register::R6 && !(lo..=hi).contains(&address) => {
+                Err(VmError::StackOverflow { pc, address })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Like `step`, except a TRAP is one step however it is serviced: with
+    /// `vectored_traps` its service routine is run until it returns to the
+    /// word after the TRAP. A completed trap reports `Trapped`; a halt,
+    /// breakpoint or other stop inside the routine is reported as usual.
+    pub fn step_over_trap(&mut self) -> Result<StepResult, VmError> {
+        let pc = self.pc();
+        let Instruction::Trap { vector } = decode(self.peek(pc)) else {
+            return self.step();
+        };
+        let written = self.output_written;
+        let back = pc.wrapping_add(1);
+
+        let mut result = self.step()?;
+        if result == StepResult::Continue && self.pc() != back {
+            result = self.run_until_pc(back, u64::MAX)?;
+        }
+
+        match result {
+            StepResult::Continue | StepResult::ReachedTarget => Ok(StepResult::Trapped {
+                vector,
+                output: (self.output_written - written) as usize,
+            }),
+            other => Ok(other),
+        }
+    }
+
+    /* one step, then report anything that should hand control back */
+    fn step_checked(&mut self, target: Option<u16>) -> Result<Option<StepResult>, VmError> {
+        match self.step()? {
+            StepResult::Halted => return Ok(Some(StepResult::Halted)),
+            StepResult::NeedsInput => return Ok(Some(StepResult::NeedsInput)),
+            changed @ StepResult::RegChanged { .. } => return Ok(Some(changed)),
+            _ => {}
+        }
+
+        if let Some(address) = self.watch_hit {
+            return Ok(Some(StepResult::Watchpoint(address)));
+        }
+
+        let pc = self.pc();
+        if target == Some(pc) {
+            Ok(Some(StepResult::ReachedTarget))
+        } else if self.breakpoints.contains(&pc)
+            && self.break_conditions.get(&pc).is_none_or(|cond| cond(self))
+        {
+            Ok(Some(StepResult::Breakpoint(pc)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Runs without ever blocking on the keyboard: returns `NeedsInput`
+    /// instead of letting GETC/IN wait, so an event loop can resume the
+    /// program once input arrives.
+    pub fn run_step_until_io(&mut self) -> RunState {
+        let _stdout = self.lock_stdout();
+        loop {
+            if self.waiting_for_input() {
+                return RunState::NeedsInput;
+            }
+
+            match self.step() {
+                Ok(StepResult::Halted) => return RunState::Halted,
+                Ok(_) => {}
+                Err(err) => return RunState::Faulted(err),
+            }
+        }
+    }
+
+    /// Like `run_step_until_io`, but also hands back control as soon as an
+    /// instruction prints, returning `Output` with the bytes so a UI can
+    /// render them incrementally. Output is not sent to the writer.
+    pub fn run_until_output(&mut self) -> RunState {
+        /* a halt or fault that came with output is reported on the next call */
+        if let Some(state) = self.pending_state.take() {
+            return state;
+        }
+
+        self.captured = Some(Vec::new());
+        let state = loop {
+            if self.waiting_for_input() {
+                break RunState::NeedsInput;
+            }
+
+            match self.step() {
+                Ok(StepResult::Halted) => break RunState::Halted,
+                Ok(_) => {}
+                Err(err) => break RunState::Faulted(err),
+            }
+
+            if self
+                .captured
+                .as_ref()
+                .is_some_and(|chunk| !chunk.is_empty())
+            {
+                break RunState::Output(Vec::new());
+            }
+        };
+
+        let chunk = self.captured.take().unwrap_or_default();
+        match state {
+            RunState::Output(_) => RunState::Output(chunk),
+            _ if chunk.is_empty() => state,
+            _ => {
+                self.pending_state = Some(state);
+                RunState::Output(chunk)
+            }
+        }
+    }
+
+    /* the next instruction would block reading a key in Rust; under
+     * vectored traps the OS code polls KBSR instead */
+    fn waiting_for_input(&self) -> bool {
+        if self.options.vectored_traps {
+            return false;
+        }
+
+        let is_input_trap = match decode(self.peek(self.pc())) {
+            Instruction::Trap { vector } => matches!(
+                TrapCode::try_from(vector as u16),
+                Ok(TrapCode::Getc | TrapCode::In)
+            ),
+            _ => false,
+        };
+
+        is_input_trap && self.pending_keys.is_empty()
+    }
+
+    /// Calls `hook` every time an ADD overflows the signed 16-bit range.
+    /// Execution is unaffected; the wrapped result is still stored.
+    pub fn on_overflow(&mut self, hook: impl FnMut(&Overflow) + 'static) {
+        self.overflow_hook = Some(Box::new(hook));
+    }
+
+    /// Calls `hook` for every fetch, read and store that goes through the
+    /// memory path, devices included, in the order they happen. Loading
+    /// and `peek` bypass it. Removed with `clear_mem_access_hook`.
+    pub fn on_mem_access(&mut self, hook: impl FnMut(&Access) + 'static) {
+        self.access_hook = Some(Box::new(hook));
+    }
+
+    pub fn clear_mem_access_hook(&mut self) {
+        self.access_hook = None;
+    }
+
+    /// Lets `handler` deal with an illegal instruction (`IllegalOpcode`, or
+    /// `Reserved`/`UnimplementedRti` under `ReservedOpPolicy::Error`)
+    /// instead of failing straight away. It gets the VM, with the PC
+    /// already past the word, and the word itself; it may change any state
+    /// before answering. Without a handler the error is returned as usual.
+    pub fn on_fault(&mut self, handler: impl FnMut(&mut VM, u16) -> FaultAction + 'static) {
+        self.fault_handler = Some(Box::new(handler));
+    }
+
+    /// Adds a system call: `TRAP vector` calls `hook` with R0, R1 and R2
+    /// and puts its return value in R0, setting the flags from it. Only
+    /// vectors without a built-in routine can be hooked; hooks take
+    /// priority over the trap vector table.
+    pub fn on_trap(&mut self, vector: u8, hook: impl FnMut([u16; 3]) -> u16 + 'static) {
+        self.trap_hooks.insert(vector, Box::new(hook));
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+        self.break_conditions.remove(&address);
+    }
+
+    /// Like `add_breakpoint`, but only stops when `cond` returns true for the
+    /// machine state just before the instruction at `address` runs.
+    pub fn add_conditional_breakpoint(&mut self, address: u16, cond: Box<dyn Fn(&VM) -> bool>) {
+        self.breakpoints.insert(address);
+        self.break_conditions.insert(address, cond);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.break_conditions.remove(&address);
+        self.breakpoints.remove(&address)
+    }
+
+    /// Breakpoint addresses in ascending order.
+    pub fn breakpoints(&self) -> Vec<u16> {
+        self.breakpoints.iter().copied().collect()
+    }
+
+    pub fn clear_all_breakpoints(&mut self) {
+        self.breakpoints.clear();
+        self.break_conditions.clear();
+    }
+
+    /// Stops the run loops right after an instruction stores to `address`.
+    pub fn add_watchpoint(&mut self, address: u16) {
+        self.watchpoints.insert(address);
+    }
+
+    pub fn remove_watchpoint(&mut self, address: u16) -> bool {
+        self.watchpoints.remove(&address)
+    }
+
+    /// Watchpoint addresses in ascending order.
+    pub fn watchpoints(&self) -> Vec<u16> {
+        self.watchpoints.iter().copied().collect()
+    }
+
+    pub fn clear_all_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// Makes `step` return `RegChanged` after any instruction that changes
+    /// `reg`. Watching `Pc` stops after every instruction.
+    pub fn add_reg_watch(&mut self, reg: Register) {
+        if !self.reg_watches.contains(&reg) {
+            self.reg_watches.push(reg);
+        }
+    }
+
+    pub fn remove_reg_watch(&mut self, reg: Register) -> bool {
+        let len = self.reg_watches.len();
+        self.reg_watches.retain(|&watched| watched != reg);
+        self.reg_watches.len() != len
+    }
+
+    /// Creates a VM with the image at `path` loaded and the PC on its origin.
+    pub fn from_image(path: &str) -> Result<VM, LoadError> {
+        let mut vm = VM::new();
+        let origin = vm.read_image(path)?;
+        vm.set_pc(origin);
+        Ok(vm)
+    }
+
+    /// Loads an LC-3 object image: a big-endian origin word followed by
+    /// big-endian payload words copied into memory starting at the origin.
+    /// Returns the origin.
+    pub fn read_image(&mut self, filename: &str) -> Result<u16, LoadError> {
+        self.read_image_from(File::open(filename)?)
+    }
+
+    /// Same as `read_image`, but from any byte source such as `io::stdin()`.
+    /// With the `gzip` feature, gzip-compressed images (`.obj.gz`) are
+    /// recognised by their header and decompressed first; anything else
+    /// loads as a plain image.
+    pub fn read_image_from<R: Read>(&mut self, mut reader: R) -> Result<u16, LoadError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        let (origin, words) = parse_image_file(bytes)?;
+        self.load_image_words(origin, &words)?;
+        Ok(origin)
+    }
+
+    /// Loads an in-memory object image whose words are stored in `order`.
+    /// Standard images are big-endian; little-endian is for off-spec tools.
+    pub fn load_image_bytes_with_order(
+        &mut self,
+        bytes: &[u8],
+        order: ByteOrder,
+    ) -> Result<u16, LoadError> {
+        let (origin, words) = parse_image(bytes, order)?;
+        self.load_image_words(origin, &words)?;
+        Ok(origin)
+    }
+
+    /// Loads the hand-writable text format: an optional `.ORIG x3000`
+    /// (defaulting to `DEFAULT_ORIGIN`), then one hex word per line (`x`
+    /// or `0x` prefix optional), `;` comments and blank lines ignored, and
+    /// an optional `.END` after which nothing is read. Returns the origin.
+    pub fn load_hex_text(&mut self, text: &str) -> Result<u16, LoadError> {
+        let mut origin = None;
+        let mut words = Vec::new();
+
+        for (index, raw) in text.lines().enumerate() {
+            let line = index + 1;
+            let code = raw.split(';').next().unwrap_or("").trim();
+            let parse_error = |message: &str| LoadError::Parse {
+                line,
+                message: message.to_string(),
+            };
+
+            if code.is_empty() {
+                continue;
+            }
+
+            let mut fields = code.split_whitespace();
+            match fields.next().map(str::to_ascii_uppercase).as_deref() {
+                Some(".END") => break,
+                Some(".ORIG") => {
+                    if origin.is_some() || !words.is_empty() {
+                        return Err(parse_error(".ORIG must come once, before any word"));
+                    }
+                    let value = fields
+                        .next()
+                        .ok_or_else(|| parse_error(".ORIG needs an address"))?;
+                    origin = Some(
+                        parse_hex_word(value).ok_or_else(|| parse_error("bad .ORIG address"))?,
+                    );
+                }
+                Some(_) => {
+                    words.push(
+                        parse_hex_word(code).ok_or_else(|| parse_error("expected one hex word"))?,
+                    );
+                }
+                None => {}
+            }
+        }
+
+        let origin = origin.unwrap_or(DEFAULT_ORIGIN);
+        self.load_image_words(origin, &words)?;
+        Ok(origin)
+    }
+
+    /* what every image loader does once parsed: reject payloads that don't fit */
+    pub(crate) fn load_image_words(&mut self, origin: u16, words: &[u16]) -> Result<(), LoadError> {
+        /* a header with no payload always fits and is a valid, empty
+         * image, at any origin; an origin of xFFFF with any payload is
+         * out of range, as is a payload running past the last cell */
+        let past_end = self.cell(origin) + words.len() > self.memory.len();
+        if !words.is_empty() && (origin == 0xFFFF || past_end) {
+            return Err(LoadError::OutOfRange {
+                origin,
+                len: words.len(),
+            });
+        }
+
+        debug!("loaded {} words at x{:04X}", words.len(), origin);
+        self.load_words(origin, words);
+        Ok(())
+    }
+
+    /// Saves `len` words starting at `start` as an object image that
+    /// `read_image` loads back unchanged. Words past the end of memory
+    /// are not written.
+    pub fn write_image(&self, path: &str, start: u16, len: u16) -> io::Result<()> {
+        self.write_image_to(BufWriter::new(File::create(path)?), start, len)
+    }
+
+    /// Same as `write_image`, but into any byte sink.
+    pub fn write_image_to<W: Write>(&self, mut writer: W, start: u16, len: u16) -> io::Result<()> {
+        let start = self.cell(start);
+        let end = (start + len as usize).min(self.memory.len());
+
+        writer.write_all(&(start as u16).to_be_bytes())?;
+        for word in &self.memory[start..end] {
+            writer.write_all(&word.to_be_bytes())?;
+        }
+
+        writer.flush()
+    }
+
+    /// Copies `words` into memory starting at `origin`; anything that
+    /// would run past the end of memory is dropped.
+    pub fn load_words(&mut self, origin: u16, words: &[u16]) {
+        let start = self.cell(origin);
+        let len = words.len().min(self.memory.len() - start);
+        self.memory[start..start + len].copy_from_slice(&words[..len]);
+        self.segments.push(Segment {
+            origin: start as u16,
+            len,
+        });
+    }
+
+    /// Loads the built-in LC-3 OS (see `os`): the trap vector table at
+    /// `trap_base` and the service routines at `os::ROUTINES_ORIGIN`. It
+    /// turns on `vectored_traps` and `blocking_keyboard` so every TRAP runs
+    /// real LC-3 code. The routines store to their own save cells, so leave
+    /// `protect_system_region` off.
+    pub fn install_default_os(&mut self) {
+        self.load_words(self.options.trap_base, &os::trap_table());
+        self.load_words(os::ROUTINES_ORIGIN, &os::routines());
+        self.options.vectored_traps = true;
+        self.options.blocking_keyboard = true;
+    }
+
+    /// Runs an OS image and a user program loaded together. Execution
+    /// starts at `os_origin`, not `DEFAULT_ORIGIN`: the OS sets itself up
+    /// and enters the user program by jumping to it, typically `JMP` to
+    /// x3000. `vectored_traps` is turned on, so the program's TRAPs go
+    /// through the vector table the OS image loaded, and the run ends when
+    /// any code halts the machine. Returns R0 at that point, like `run`.
+    pub fn run_layered(&mut self, os_origin: u16) -> Result<u16, VmError> {
+        self.options.vectored_traps = true;
+        self.set_pc(os_origin);
+        self.run()
+    }
+
+    /// Return addresses of the calls currently in progress, outermost first.
+    ///
+    /// Built from JSR, JSRR and vectored TRAP pushing the link and `RET`
+    /// popping it, so code that manages R7 by hand can confuse it.
+    pub fn call_stack(&self) -> Vec<u16> {
+        self.call_stack.clone()
+    }
+
+    /// Sets `len` cells from `start` to `value`, wrapping past xFFFF to
+    /// x0000. Like loading, it writes memory directly: no devices are
+    /// triggered and no protection or watchpoints apply.
+    pub fn fill(&mut self, start: u16, len: u16, value: u16) {
+        for i in 0..len {
+            let cell = self.cell(start.wrapping_add(i));
+            self.memory[cell] = value;
+        }
+    }
+
+    /// Every region written by a load, in load order.
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    pub fn set_register(&mut self, reg: Register, value: u16) {
+        self.registers[reg as usize] = value;
+    }
+
+    /// Queues a key press as if typed on the keyboard. It is visible to
+    /// programs polling KBSR/KBDR and is consumed before the terminal by
+    /// GETC and IN.
+    pub fn push_key(&mut self, byte: u8) {
+        self.pending_keys.push_back(byte);
+    }
+
+    /// Replaces the keyboard GETC and IN read from once no pushed key is
+    /// waiting. Defaults to `TerminalInput`.
+    pub fn set_input(&mut self, input: impl Input + 'static) {
+        self.input = Box::new(input);
+    }
+
+    /// Queues every byte of `input` as key presses, in order.
+    pub fn feed_input(&mut self, input: &[u8]) {
+        self.pending_keys.extend(input);
+    }
+
+    /// Puts `device` at `address`: program loads and stores there call it
+    /// instead of touching memory. Takes precedence over the built-in
+    /// registers, so it can also replace one; `peek` still reads memory.
+    pub fn map_device(&mut self, address: u16, device: Box<dyn MmioDevice>) {
+        self.devices.insert(address, device);
+    }
+
+    pub fn mem_read(&mut self, address: u16) -> u16 {
+        let value = self.read_memory(address);
+        self.notify_access(AccessKind::Read, address, value);
+        value
+    }
+
+    fn notify_access(&mut self, kind: AccessKind, address: u16, value: u16) {
+        if let Some(hook) = &mut self.access_hook {
+            hook(&Access {
+                kind,
+                address,
+                value,
+            });
+        }
+    }
+
+    /* mem_read without reporting the access */
+    fn read_memory(&mut self, address: u16) -> u16 {
+        if let Some(device) = self.devices.get_mut(&address) {
+            return device.read();
+        }
+
+        match address {
+            /* ready bit set while a key is queued; polls the input for one first */
+            KBSR => {
+                if self.pending_keys.is_empty() {
+                    let key = if self.options.blocking_keyboard {
+                        self.input.getchar()
+                    } else {
+                        self.input.try_getchar()
+                    };
+                    self.pending_keys.extend(key);
+                }
+
+                if self.pending_keys.is_empty() {
+                    0
+                } else {
+                    1 << 15
+                }
+            }
+            /* reading the data register consumes the key */
+            KBDR => self.pending_keys.pop_front().map_or(0, u16::from),
+            /* the display is always ready to accept a character */
+            DSR => 1 << 15,
+            RNG => self.next_random(),
+            _ => self.memory[self.cell(address)],
+        }
+    }
+
+    /* next key for GETC/IN: pushed keys first, then the terminal */
+    fn read_key(&mut self) -> Result<u16, VmError> {
+        match self.pending_keys.pop_front() {
+            Some(byte) => Ok(byte as u16),
+            None => self
+                .input
+                .getchar()
+                .map(u16::from)
+                .ok_or(VmError::InputClosed),
+        }
+    }
+
+    /// Replaces where program output (OUT, PUTS, PUTSP, IN's prompt and
+    /// echo, HALT, DDR) goes. Defaults to stdout, which `run` and the other
+    /// run loops keep locked until they return. Each trap's output reaches
+    /// the writer in one write followed by a flush.
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.output = Box::new(output);
+        self.output_is_stdout = false;
+    }
+
+    /* every character the program prints goes through here, flushed per trap */
+    fn write_output(&mut self, bytes: &[u8]) -> Result<(), VmError> {
+        self.output_written += bytes.len() as u64;
+
+        if let Some(chunk) = &mut self.captured {
+            chunk.extend_from_slice(bytes);
+            return Ok(());
+        }
+
+        let result = if self.options.crlf_output {
+            bytes.split_inclusive(|&b| b == b'\n').try_for_each(|line| {
+                match line.strip_suffix(b"\n") {
+                    Some(text) => {
+                        self.output.write_all(text)?;
+                        self.output.write_all(b"\r\n")
+                    }
+                    None => self.output.write_all(line),
+                }
+            })
+        } else {
+            self.output.write_all(bytes)
+        };
+
+        result
+            .and_then(|()| self.output.flush())
+            .map_err(|err| VmError::OutputFailed(err.kind()))
+    }
+
+    /// Restarts the random number register at xFE08 from `seed`, so two
+    /// VMs seeded alike read the same sequence.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng_state = seed;
+    }
+
+    fn next_random(&mut self) -> u16 {
+        splitmix64(&mut self.rng_state) as u16
+    }
+
+    pub fn mem_write(&mut self, address: u16, value: u16) -> Result<(), VmError> {
+        if self.options.protect_system_region && address < USER_SPACE_START {
+            return Err(VmError::ProtectionFault(address));
+        }
+
+        if self.options.write_quota == Some(self.writes) {
+            return Err(VmError::WriteQuotaExceeded(address));
+        }
+        self.writes += 1;
+        self.notify_access(AccessKind::Write, address, value);
+
+        if self.watchpoints.contains(&address) {
+            self.watch_hit = Some(address);
+        }
+
+        if self.options.warn_self_modifying && self.coverage.contains(address) {
+            warn!("store to already executed instruction at x{:04X}", address);
+        }
+
+        if let Some(device) = self.devices.get_mut(&address) {
+            device.write(value);
+            return Ok(());
+        }
+
+        match address {
+            /* writing the display data register emits its low byte */
+            DDR => self.write_output(&[value as u8])?,
+            _ => {
+                /* clearing the MCR clock bit halts the machine after this instruction */
+                if address == MCR && value & 1 << 15 == 0 {
+                    self.clock_stopped = true;
+                }
+
+                let cell = self.cell(address);
+                if let Some(undo) = &mut self.recording {
+                    undo.stores.push((cell, self.memory[cell]));
+                }
+                self.memory[cell] = value;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get_register(&self, reg: Register) -> u16 {
+        self.registers[reg as usize]
+    }
+
+    /// `get_register` read as a signed value, e.g. xFFFF is -1.
+    pub fn reg_signed(&self, reg: Register) -> i16 {
+        to_signed(self.get_register(reg))
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.get_register(Register::Pc)
+    }
+
+    pub fn set_pc(&mut self, value: u16) {
+        self.set_register(Register::Pc, value);
+    }
+
+    /// The condition register: exactly one of the `ConditionFlag` bits,
+    /// unless `set_cond_raw` put something else there.
+    pub fn cond(&self) -> u16 {
+        self.get_register(Register::Cond)
+    }
+
+    /// Sets the condition register to just `flag`, as if an instruction
+    /// had produced a value of that sign.
+    pub fn set_cond(&mut self, flag: ConditionFlag) {
+        self.set_register(Register::Cond, flag as u16);
+    }
+
+    /// Sets the condition register to any combination of the N, Z and P
+    /// bits, including none or several, which no instruction produces.
+    /// For testing how code behaves in such states; bits above the low
+    /// three are dropped.
+    pub fn set_cond_raw(&mut self, bits: u16) {
+        self.set_register(Register::Cond, bits & 0x7);
+    }
+
+    /// Reads a memory cell without any device side effects, for tooling.
+    pub fn peek(&self, address: u16) -> u16 {
+        self.memory[self.cell(address)]
+    }
+
+    /* all of memory, for whole-state comparisons */
+    pub(crate) fn memory(&self) -> &[u16] {
+        &self.memory
+    }
+
+    /* index of the memory cell an address lands on, wrapping to memory_size */
+    fn cell(&self, address: u16) -> usize {
+        address as usize & (self.memory.len() - 1)
+    }
+
+    /// One line per register, `R0`..`R7` then `PC` and `COND`.
+    pub fn dump_registers(&self) -> String {
+        let mut dump = String::new();
+
+        for (i, value) in self.registers[..8].iter().enumerate() {
+            dump.push_str(&format!("R{}   x{:04X}\n", i, value));
+        }
+        dump.push_str(&format!("PC   x{:04X}\n", self.get_register(Register::Pc)));
+        dump.push_str(&format!(
+            "COND x{:04X}\n",
+            self.get_register(Register::Cond)
+        ));
+
+        dump
+    }
+
+    /// Registers as a JSON object for graders and visualizers, e.g.
+    /// `{"registers":{"R0":0,...,"PC":12288,"COND":2}}`. Values are decimal.
+    pub fn dump_state_json(&self) -> String {
+        self.state_json(None)
+    }
+
+    /// `dump_state_json` plus `"memory":{"start":..,"words":[..]}` holding
+    /// `len` words read from `start` without device side effects.
+    pub fn dump_state_json_with_memory(&self, start: u16, len: u16) -> String {
+        self.state_json(Some((start, len)))
+    }
+
+    fn state_json(&self, region: Option<(u16, u16)>) -> String {
+        let mut fields: Vec<String> = (0..8)
+            .map(|i| format!("\"R{}\":{}", i, self.registers[i]))
+            .collect();
+        fields.push(format!("\"PC\":{}", self.pc()));
+        fields.push(format!("\"COND\":{}", self.cond()));
+
+        let mut json = format!("{{\"registers\":{{{}}}", fields.join(","));
+
+        if let Some((start, len)) = region {
+            let words: Vec<String> = (0..len)
+                .map(|i| self.peek(start.wrapping_add(i)).to_string())
+                .collect();
+            json.push_str(&format!(
+                ",\"memory\":{{\"start\":{},\"words\":[{}]}}",
+                start,
+                words.join(",")
+            ));
+        }
+
+        json.push('}');
+        json
+    }
+
+    fn update_flags(&mut self, r: Register) {
+        self.update_flags_with(self.get_register(r));
+    }
+
+    /// Sets COND from a value the caller already has in hand, saving the
+    /// register read `update_flags` would do.
+    fn update_flags_with(&mut self, val: u16) {
+        self.set_register(Register::Cond, condition_for(val) as u16);
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,2441 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use super::*;
+use crate::encode::*;
+
+/* a VM with `words` at x3000, no keyboard, and output kept for inspection */
+fn machine(words: &[u16]) -> (VM, SharedBuffer) {
+    machine_with(Options::default(), words)
+}
+
+fn machine_with(options: Options, words: &[u16]) -> (VM, SharedBuffer) {
+    let output = SharedBuffer::default();
+    let mut vm = VM::with_options(options);
+    vm.set_input(LineInput::new(io::empty()));
+    vm.set_output(output.clone());
+    vm.load_words(DEFAULT_ORIGIN, words);
+    (vm, output)
+}
+
+fn printed(output: &SharedBuffer) -> String {
+    String::from_utf8_lossy(&output.0.borrow()).into_owned()
+}
+
+const HALT: u16 = 0xF025;
+
+#[test]
+fn coverage_marks_only_the_taken_path() {
+    let options = Options {
+        track_coverage: true,
+        ..Options::default()
+    };
+    let (mut vm, _) = machine_with(
+        options,
+        &[
+            encode_and_imm(Register::R0, Register::R0, 0), // x3000 R0 = 0, Z set
+            encode_br(false, true, false, 1),              // x3001 BRz x3003
+            encode_add_imm(Register::R0, Register::R0, 1), // x3002 skipped
+            HALT,                                          // x3003
+        ],
+    );
+
+    vm.run().unwrap();
+
+    let covered: Vec<u16> = vm.executed_addresses().iter().collect();
+    assert_eq!(covered, [0x3000, 0x3001, 0x3003]);
+}
+
+#[test]
+fn coverage_is_off_by_default() {
+    let (mut vm, _) = machine(&[HALT]);
+    vm.run().unwrap();
+    assert!(vm.executed_addresses().is_empty());
+}
+
+#[test]
+fn jsrr_r7_jumps_to_the_old_r7_not_the_link() {
+    let (mut vm, _) = machine(&[encode_jsrr(Register::R7)]);
+    vm.set_register(Register::R7, 0x4000);
+
+    assert_eq!(vm.step(), Ok(StepResult::Continue));
+    assert_eq!(vm.pc(), 0x4000);
+    assert_eq!(vm.get_register(Register::R7), 0x3001);
+}
+
+#[test]
+fn jsr_links_the_incremented_pc() {
+    let (mut vm, _) = machine(&[encode_jsr(0x10)]);
+
+    vm.step().unwrap();
+    assert_eq!(vm.pc(), 0x3011);
+    assert_eq!(vm.get_register(Register::R7), 0x3001);
+}
+
+#[test]
+fn jumping_into_poisoned_memory_is_an_illegal_opcode() {
+    let options = Options {
+        mem_init: MemInit::Poison(0xDEAD),
+        ..Options::default()
+    };
+    // x3000 JMP R1 with R1 = x4000, which nothing wrote
+    let (mut vm, _) = machine_with(options, &[encode_jmp(Register::R1)]);
+    vm.set_register(Register::R1, 0x4000);
+
+    assert_eq!(
+        vm.run(),
+        Err(VmError::IllegalOpcode {
+            pc: 0x4000,
+            instr: 0xDEAD,
+        })
+    );
+}
+
+#[test]
+fn zeroed_memory_executes_as_nops() {
+    let (mut vm, _) = machine(&[]);
+    assert_eq!(vm.peek(0x4000), 0);
+    assert_eq!(vm.step(), Ok(StepResult::Continue));
+}
+
+/* an object image: big-endian origin, then the words */
+fn image_bytes(origin: u16, words: &[u16]) -> Vec<u8> {
+    std::iter::once(origin)
+        .chain(words.iter().copied())
+        .flat_map(u16::to_be_bytes)
+        .collect()
+}
+
+#[test]
+fn images_load_from_any_reader() {
+    let (mut vm, output) = machine(&[]);
+    let image = image_bytes(0x3000, &crate::programs::hello_world());
+
+    assert_eq!(vm.read_image_from(&image[..]).unwrap(), 0x3000);
+    vm.run().unwrap();
+    assert_eq!(printed(&output), "Hello, World!\nHALT\n");
+}
+
+#[test]
+fn histogram_counts_each_opcode_executed() {
+    let add = encode_add_imm(Register::R0, Register::R0, 1);
+    let (mut vm, _) = machine(&[add, add, add, add, add, HALT]);
+
+    vm.run().unwrap();
+
+    let histogram = vm.opcode_histogram();
+    assert_eq!(histogram[OpCode::Add as usize], 5);
+    assert_eq!(histogram[OpCode::Trap as usize], 1);
+    assert_eq!(histogram.iter().sum::<u64>(), 6);
+
+    vm.reset();
+    assert_eq!(vm.opcode_histogram(), [0; 16]);
+}
+
+#[test]
+fn sti_stores_to_the_address_held_in_the_pointer_cell() {
+    let (mut vm, _) = machine(&[
+        encode_sti(Register::R0, 1), // x3000 STI R0, x3002
+        HALT,                        // x3001
+        0x4000,                      // x3002 pointer
+    ]);
+    vm.set_register(Register::R0, 0x1234);
+
+    vm.run().unwrap();
+    assert_eq!(vm.peek(0x4000), 0x1234);
+    assert_eq!(vm.peek(0x3002), 0x4000);
+}
+
+#[test]
+fn sti_through_a_pointer_to_ddr_prints() {
+    let (mut vm, output) = machine(&[
+        encode_sti(Register::R0, 1), // x3000 STI R0, x3002
+        HALT,                        // x3001
+        DDR,                         // x3002 pointer
+    ]);
+    vm.set_register(Register::R0, u16::from(b'!'));
+
+    vm.run().unwrap();
+    assert_eq!(printed(&output), "!HALT\n");
+    assert_eq!(vm.peek(DDR), 0);
+}
+
+/* a device register that always reads the same word */
+struct Constant(u16);
+
+impl MmioDevice for Constant {
+    fn read(&mut self) -> u16 {
+        self.0
+    }
+}
+
+#[test]
+fn unterminated_strings_stop_after_one_pass_over_memory() {
+    for (trap, bytes_per_word) in [(0xF022, 1), (0xF024, 2)] {
+        let (mut vm, output) = machine(&[]);
+        vm.fill(0x0000, 0xFFFF, 0x4141);
+        vm.fill(0xFFFF, 1, 0x4141);
+        /* the device registers would otherwise read as terminators */
+        for address in [KBSR, KBDR, DSR, RNG] {
+            vm.map_device(address, Box::new(Constant(0x4141)));
+        }
+        vm.load_words(DEFAULT_ORIGIN, &[trap, HALT]);
+
+        assert_eq!(vm.run(), Ok(0));
+        let len = output.0.borrow().len();
+        assert_eq!(len, MEMORY_MAX * bytes_per_word + "HALT\n".len());
+    }
+}
+
+#[test]
+fn update_flags_with_matches_update_flags() {
+    let (mut vm, _) = machine(&[]);
+
+    for (value, flag) in [
+        (0x8000, ConditionFlag::Neg),
+        (0xFFFF, ConditionFlag::Neg),
+        (0, ConditionFlag::Zro),
+        (1, ConditionFlag::Pos),
+        (0x7FFF, ConditionFlag::Pos),
+    ] {
+        vm.set_register(Register::R3, value);
+        vm.update_flags(Register::R3);
+        let read_back = vm.cond();
+
+        vm.set_cond_raw(0);
+        vm.update_flags_with(value);
+        assert_eq!(vm.cond(), read_back, "x{:04X}", value);
+        assert_eq!(vm.cond(), flag as u16, "x{:04X}", value);
+    }
+}
+
+#[test]
+fn every_register_reads_back_what_was_set() {
+    let (mut vm, _) = machine(&[]);
+    let all = (0..10).map(|index| Register::try_from(index).unwrap());
+
+    for (value, reg) in (0x100..).zip(all.clone()) {
+        vm.set_register(reg, value);
+    }
+    for (value, reg) in (0x100..).zip(all) {
+        assert_eq!(vm.get_register(reg), value, "{:?}", reg);
+    }
+    assert!(Register::try_from(10).is_err());
+}
+
+#[test]
+fn stores_below_x3000_fault_under_protection() {
+    let options = Options {
+        protect_system_region: true,
+        ..Options::default()
+    };
+    let (mut vm, _) = machine_with(options, &[]);
+    // x0030 ST R0, x0020
+    vm.load_words(0x0030, &[encode_st(Register::R0, -17)]);
+    vm.set_pc(0x0030);
+
+    assert_eq!(vm.step(), Err(VmError::ProtectionFault(0x0020)));
+    assert_eq!(vm.mem_write(0x3000, 1), Ok(()));
+}
+
+#[test]
+fn loading_and_unprotected_stores_reach_the_system_region() {
+    let (mut vm, _) = machine(&[]);
+    vm.load_words(0x0030, &[encode_st(Register::R0, -17)]);
+    vm.set_pc(0x0030);
+    vm.set_register(Register::R0, 7);
+
+    assert_eq!(vm.step(), Ok(StepResult::Continue));
+    assert_eq!(vm.peek(0x0020), 7);
+}
+
+/* a file name in the temp directory unique to this test process */
+fn temp_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(format!("lc3-vm-{}-{}", std::process::id(), name))
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[test]
+fn from_image_loads_and_starts_at_the_origin() {
+    let path = temp_path("from_image.obj");
+    let words = [
+        encode_and_imm(Register::R0, Register::R0, 0),
+        encode_add_imm(Register::R0, Register::R0, 9),
+        HALT,
+    ];
+    std::fs::write(&path, image_bytes(0x4000, &words)).unwrap();
+
+    let mut vm = VM::from_image(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    vm.set_output(io::sink());
+
+    assert_eq!(vm.pc(), 0x4000);
+    assert_eq!(vm.run(), Ok(9));
+}
+
+#[test]
+fn from_image_tells_missing_files_from_bad_ones() {
+    let path = temp_path("from_image_bad.obj");
+    std::fs::write(&path, [0x30, 0x00, 0x12]).unwrap();
+
+    assert!(matches!(
+        VM::from_image(&temp_path("no_such_image.obj")),
+        Err(LoadError::NotFound)
+    ));
+    assert!(matches!(
+        VM::from_image(&path),
+        Err(LoadError::BadFormat(_))
+    ));
+    std::fs::remove_file(&path).unwrap();
+}
+
+/* log records emitted on this thread while `run` is called */
+fn logged<T>(run: impl FnOnce() -> T) -> (T, Vec<(log::Level, String)>) {
+    use std::sync::Once;
+
+    thread_local! {
+        static RECORDS: RefCell<Vec<(log::Level, String)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    struct Capture;
+
+    impl log::Log for Capture {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            let entry = (record.level(), record.args().to_string());
+            RECORDS.with(|records| records.borrow_mut().push(entry));
+        }
+
+        fn flush(&self) {}
+    }
+
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        log::set_logger(&Capture).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+    });
+
+    RECORDS.with(|records| records.borrow_mut().clear());
+    let result = run();
+    (result, RECORDS.with(|records| records.take()))
+}
+
+#[test]
+fn halt_emits_a_debug_record() {
+    let (mut vm, _) = machine(&[HALT]);
+
+    let (result, records) = logged(|| vm.run());
+    assert_eq!(result, Ok(0));
+    assert!(records.contains(&(log::Level::Debug, String::from("halt at x3000"))));
+    assert!(records.contains(&(log::Level::Trace, String::from("trap x25 at x3000"))));
+}
+
+#[test]
+fn illegal_instructions_log_warnings() {
+    for (word, message) in [
+        (0xD000, "reserved opcode xD000 executed at x3000"),
+        (0x8000, "unimplemented RTI executed at x3000"),
+        (0xF0FF, "unknown trap xFF at x3000"),
+    ] {
+        let (mut vm, _) = machine(&[word]);
+
+        let (result, records) = logged(|| vm.step());
+        assert!(result.is_err());
+        assert!(
+            records.contains(&(log::Level::Warn, String::from(message))),
+            "{:?}",
+            records
+        );
+    }
+}
+
+#[test]
+fn unknown_traps_log_a_warning() {
+    let (mut vm, _) = machine(&[0xF0FF]);
+
+    let (result, records) = logged(|| vm.step());
+    assert!(result.is_err());
+    assert!(
+        records.contains(&(log::Level::Warn, String::from("unknown trap xFF at x3000"))),
+        "{:?}",
+        records
+    );
+}
+
+#[test]
+fn loading_logs_a_summary() {
+    let (mut vm, _) = machine(&[]);
+
+    let (_, records) = logged(|| {
+        vm.load_image_bytes_with_order(&image_bytes(0x3000, &[1, 2]), ByteOrder::BigEndian)
+    });
+    assert!(records.contains(&(log::Level::Debug, String::from("loaded 2 words at x3000"))));
+}
+
+#[test]
+fn pushed_keys_show_up_in_kbsr_and_kbdr() {
+    let (mut vm, _) = machine(&[]);
+    assert_eq!(vm.mem_read(KBSR), 0);
+
+    vm.push_key(b'q');
+    assert_eq!(vm.mem_read(KBSR), 0x8000);
+    assert_eq!(vm.mem_read(KBDR), u16::from(b'q'));
+    assert_eq!(vm.mem_read(KBSR), 0);
+}
+
+#[test]
+fn a_polling_loop_reads_a_pushed_key() {
+    let (mut vm, _) = machine(&[
+        encode_ldi(Register::R1, 3),      // x3000 POLL: LDI R1, KBSRP
+        encode_br(false, true, true, -2), // x3001 BRzp POLL
+        encode_ldi(Register::R0, 2),      // x3002 LDI R0, KBDRP
+        HALT,                             // x3003
+        KBSR,                             // x3004 KBSRP
+        KBDR,                             // x3005 KBDRP
+    ]);
+    vm.push_key(b'k');
+
+    assert_eq!(vm.run(), Ok(u16::from(b'k')));
+}
+
+#[test]
+fn write_image_round_trips_through_read_image() {
+    let path = temp_path("round_trip.obj");
+    let words = [0x1234, 0xF025, 0x0000, 0xFFFF, 0x8001];
+    let (mut vm, _) = machine(&[]);
+    vm.load_words(0x4000, &words);
+
+    vm.write_image(&path, 0x4000, words.len() as u16).unwrap();
+    let bytes = std::fs::read(&path).unwrap();
+    assert_eq!(bytes, image_bytes(0x4000, &words));
+
+    let (mut copy, _) = machine(&[]);
+    assert_eq!(copy.read_image(&path).unwrap(), 0x4000);
+    std::fs::remove_file(&path).unwrap();
+    assert!(copy.diff(&vm).is_empty());
+}
+
+#[test]
+fn write_image_stops_at_the_end_of_memory() {
+    let (mut vm, _) = machine(&[]);
+    vm.load_words(0xFFFE, &[1, 2]);
+
+    let mut bytes = Vec::new();
+    vm.write_image_to(&mut bytes, 0xFFFE, 4).unwrap();
+    assert_eq!(bytes, image_bytes(0xFFFE, &[1, 2]));
+}
+
+#[test]
+fn typed_pc_and_cond_accessors_match_the_register_file() {
+    let (mut vm, _) = machine(&[]);
+
+    vm.set_pc(0x4242);
+    assert_eq!(vm.pc(), 0x4242);
+    assert_eq!(vm.get_register(Register::Pc), 0x4242);
+
+    vm.set_register(Register::R2, 0xFFFE);
+    vm.update_flags(Register::R2);
+    assert_eq!(vm.cond(), ConditionFlag::Neg as u16);
+    assert_eq!(vm.cond(), vm.get_register(Register::Cond));
+}
+
+#[test]
+fn run_until_pc_stops_before_the_target_executes() {
+    let add = encode_add_imm(Register::R0, Register::R0, 1);
+    let (mut vm, _) = machine(&[add, add, add, add, add, add, HALT]);
+
+    assert_eq!(vm.run_until_pc(0x3005, 100), Ok(StepResult::ReachedTarget));
+    assert_eq!(vm.pc(), 0x3005);
+    assert_eq!(vm.get_register(Register::R0), 5);
+}
+
+#[test]
+fn run_until_pc_reports_the_budget_halt_and_breakpoints() {
+    let add = encode_add_imm(Register::R0, Register::R0, 1);
+    let (mut vm, _) = machine(&[add, add, add, HALT]);
+
+    assert_eq!(vm.run_until_pc(0x3003, 2), Ok(StepResult::LimitReached));
+    assert_eq!(vm.pc(), 0x3002);
+
+    vm.reset();
+    vm.add_breakpoint(0x3001);
+    assert_eq!(
+        vm.run_until_pc(0x3003, 100),
+        Ok(StepResult::Breakpoint(0x3001))
+    );
+
+    vm.reset();
+    vm.clear_all_breakpoints();
+    assert_eq!(vm.run_until_pc(0x5000, 100), Ok(StepResult::Halted));
+}
+
+#[test]
+fn storing_over_executed_code_warns_when_enabled() {
+    let program = [
+        encode_add_imm(Register::R0, Register::R0, 1), // x3000
+        encode_st(Register::R0, -2),                   // x3001 ST R0, x3000
+        encode_st(Register::R0, 1),                    // x3002 ST R0, x3004
+        HALT,                                          // x3003
+    ];
+    let warning = (
+        log::Level::Warn,
+        String::from("store to already executed instruction at x3000"),
+    );
+
+    let options = Options {
+        warn_self_modifying: true,
+        ..Options::default()
+    };
+    let (mut vm, _) = machine_with(options, &program);
+    let (_, records) = logged(|| vm.run());
+    let warnings: Vec<_> = records
+        .iter()
+        .filter(|(level, _)| *level == log::Level::Warn)
+        .collect();
+    assert_eq!(warnings, [&warning]);
+
+    let (mut vm, _) = machine(&program);
+    let (_, records) = logged(|| vm.run());
+    assert!(!records.contains(&warning));
+}
+
+#[test]
+fn run_returns_r0_at_halt() {
+    let (mut vm, _) = machine(&[
+        encode_and_imm(Register::R0, Register::R0, 0),
+        encode_add_imm(Register::R0, Register::R0, 7),
+        HALT,
+    ]);
+
+    assert_eq!(vm.run(), Ok(7));
+}
+
+#[test]
+fn run_step_until_io_pauses_for_getc_and_resumes() {
+    let (mut vm, output) = machine(&[
+        0xF020, // x3000 GETC
+        0xF021, // x3001 OUT
+        0xF020, // x3002 GETC
+        0xF021, // x3003 OUT
+        HALT,
+    ]);
+
+    assert_eq!(vm.run_step_until_io(), RunState::NeedsInput);
+    assert_eq!(vm.pc(), 0x3000);
+
+    vm.push_key(b'o');
+    assert_eq!(vm.run_step_until_io(), RunState::NeedsInput);
+    assert_eq!(vm.pc(), 0x3002);
+
+    vm.feed_input(b"k");
+    assert_eq!(vm.run_step_until_io(), RunState::Halted);
+    assert_eq!(printed(&output), "okHALT\n");
+}
+
+#[test]
+fn run_step_until_io_reports_faults() {
+    let (mut vm, _) = machine(&[0xD000]);
+
+    assert_eq!(
+        vm.run_step_until_io(),
+        RunState::Faulted(VmError::Reserved {
+            pc: 0x3000,
+            instr: 0xD000,
+        })
+    );
+}
+
+#[test]
+fn trap_base_moves_the_vector_table() {
+    for (trap_base, expected) in [(TRAP_VECTOR_BASE, 0x0400), (0x1000, 0x0500)] {
+        let options = Options {
+            vectored_traps: true,
+            trap_base,
+            ..Options::default()
+        };
+        let (mut vm, _) = machine_with(options, &[0xF030]);
+        vm.load_words(0x0030, &[0x0400]);
+        vm.load_words(0x1030, &[0x0500]);
+
+        vm.step().unwrap();
+        assert_eq!(vm.pc(), expected, "trap_base x{:04X}", trap_base);
+        assert_eq!(vm.get_register(Register::R7), 0x3001);
+    }
+}
+
+#[test]
+fn trap_base_defaults_to_the_spec_value() {
+    assert_eq!(Options::default().trap_base, 0x0000);
+}
+
+#[test]
+fn overflow_hook_sees_the_wrapped_result() {
+    let (mut vm, _) = machine(&[
+        encode_add(Register::R2, Register::R0, Register::R1), // x3000 x7FFF + 1
+        encode_add_imm(Register::R2, Register::R2, -1),       // x3001 x8000 - 1
+        HALT,
+    ]);
+    vm.set_register(Register::R0, 0x7FFF);
+    vm.set_register(Register::R1, 1);
+
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let sink = seen.clone();
+    vm.on_overflow(move |overflow| sink.borrow_mut().push(*overflow));
+
+    vm.step().unwrap();
+    assert_eq!(vm.get_register(Register::R2), 0x8000);
+    assert_eq!(vm.cond(), ConditionFlag::Neg as u16);
+    assert_eq!(
+        *seen.borrow(),
+        [Overflow {
+            pc: 0x3000,
+            lhs: 0x7FFF,
+            rhs: 1,
+            result: 0x8000,
+        }]
+    );
+
+    vm.step().unwrap();
+    assert_eq!(vm.get_register(Register::R2), 0x7FFF);
+    assert_eq!(seen.borrow().len(), 2);
+}
+
+#[test]
+fn overflow_hook_ignores_adds_that_fit() {
+    let (mut vm, _) = machine(&[encode_add_imm(Register::R0, Register::R0, -1), HALT]);
+    let fired = Rc::new(RefCell::new(false));
+    let flag = fired.clone();
+    vm.on_overflow(move |_| *flag.borrow_mut() = true);
+
+    vm.run().unwrap();
+    assert_eq!(vm.get_register(Register::R0), 0xFFFF);
+    assert!(!*fired.borrow());
+}
+
+#[test]
+fn step_n_runs_exactly_n_instructions() {
+    let add = encode_add_imm(Register::R0, Register::R0, 1);
+    let (mut vm, _) = machine(&[add, add, add, add, HALT]);
+
+    assert_eq!(vm.step_n(3), Ok(StepResult::Continue));
+    assert_eq!(vm.pc(), 0x3003);
+    assert_eq!(vm.cycles(), 3);
+}
+
+#[test]
+fn step_n_stops_early_on_halt_breakpoint_or_error() {
+    let add = encode_add_imm(Register::R0, Register::R0, 1);
+    let (mut vm, _) = machine(&[add, add, HALT]);
+    assert_eq!(vm.step_n(10), Ok(StepResult::Halted));
+    assert_eq!(vm.cycles(), 3);
+
+    vm.reset();
+    vm.add_breakpoint(0x3001);
+    assert_eq!(vm.step_n(10), Ok(StepResult::Breakpoint(0x3001)));
+    assert_eq!(vm.pc(), 0x3001);
+
+    let (mut vm, _) = machine(&[add, 0xD000]);
+    assert!(matches!(
+        vm.step_n(10),
+        Err(VmError::Reserved { pc: 0x3001, .. })
+    ));
+}
+
+#[test]
+fn a_header_only_image_loads_nothing() {
+    for origin in [0x3000, 0xFFFF] {
+        let (mut vm, _) = machine(&[]);
+        let before = vm.snapshot();
+
+        assert_eq!(
+            vm.load_image_bytes_with_order(&image_bytes(origin, &[]), ByteOrder::BigEndian)
+                .unwrap(),
+            origin
+        );
+        assert_eq!(vm.snapshot(), before);
+    }
+}
+
+#[test]
+fn payloads_running_past_xffff_are_out_of_range() {
+    let (mut vm, _) = machine(&[]);
+
+    assert!(matches!(
+        vm.load_image_bytes_with_order(&image_bytes(0xFFFF, &[1, 2]), ByteOrder::BigEndian),
+        Err(LoadError::OutOfRange {
+            origin: 0xFFFF,
+            len: 2,
+        })
+    ));
+    assert_eq!(vm.peek(0xFFFF), 0);
+    assert_eq!(vm.peek(0x0000), 0);
+}
+
+#[test]
+fn any_payload_at_an_origin_of_xffff_is_out_of_range() {
+    let (mut vm, _) = machine(&[]);
+
+    assert!(matches!(
+        vm.load_image_bytes_with_order(&image_bytes(0xFFFF, &[7]), ByteOrder::BigEndian),
+        Err(LoadError::OutOfRange {
+            origin: 0xFFFF,
+            len: 1,
+        })
+    ));
+    assert_eq!(vm.peek(0xFFFF), 0);
+
+    /* a payload ending on the last cell from a lower origin still fits */
+    vm.load_image_bytes_with_order(&image_bytes(0xFFFE, &[6, 7]), ByteOrder::BigEndian)
+        .unwrap();
+    assert_eq!(vm.peek(0xFFFF), 7);
+}
+
+#[test]
+fn truncated_images_are_bad_format() {
+    let (mut vm, _) = machine(&[]);
+
+    for bytes in [&[][..], &[0x30], &[0x30, 0x00, 0x12]] {
+        assert!(matches!(
+            vm.load_image_bytes_with_order(bytes, ByteOrder::BigEndian),
+            Err(LoadError::BadFormat(_))
+        ));
+    }
+}
+
+#[test]
+fn crlf_output_translates_newlines_only_when_on() {
+    let mut program = vec![0xE002, 0xF022, HALT];
+    program.extend(crate::programs::stringz("a\nb\n"));
+
+    let options = Options {
+        crlf_output: true,
+        ..Options::default()
+    };
+    let (mut vm, output) = machine_with(options, &program);
+    vm.run().unwrap();
+    assert_eq!(printed(&output), "a\r\nb\r\nHALT\r\n");
+
+    let (mut vm, output) = machine(&program);
+    vm.run().unwrap();
+    assert_eq!(printed(&output), "a\nb\nHALT\n");
+}
+
+#[test]
+fn breakpoints_and_watchpoints_can_be_listed_and_removed() {
+    let (mut vm, _) = machine(&[]);
+    for address in [0x3005, 0x3001, 0x3003] {
+        vm.add_breakpoint(address);
+    }
+    vm.add_watchpoint(0x4001);
+    vm.add_watchpoint(0x4000);
+
+    assert_eq!(vm.breakpoints(), [0x3001, 0x3003, 0x3005]);
+    assert_eq!(vm.watchpoints(), [0x4000, 0x4001]);
+
+    assert!(vm.remove_breakpoint(0x3003));
+    assert!(!vm.remove_breakpoint(0x3003));
+    assert_eq!(vm.breakpoints(), [0x3001, 0x3005]);
+    assert!(vm.remove_watchpoint(0x4000));
+    assert_eq!(vm.watchpoints(), [0x4001]);
+
+    vm.clear_all_breakpoints();
+    vm.clear_all_watchpoints();
+    assert!(vm.breakpoints().is_empty());
+    assert!(vm.watchpoints().is_empty());
+}
+
+#[test]
+fn removing_the_breakpoint_stopped_at_lets_the_run_finish() {
+    let add = encode_add_imm(Register::R0, Register::R0, 1);
+    /* x3001 loops back to x3000 once, while R0 is still 1 */
+    let (mut vm, _) = machine(&[
+        add,
+        encode_add_imm(Register::R1, Register::R0, -2),
+        encode_br(true, false, false, -3),
+        HALT,
+    ]);
+    vm.add_breakpoint(0x3000);
+    vm.add_breakpoint(0x3003);
+
+    assert_eq!(vm.run_until_break(), Ok(StepResult::Breakpoint(0x3000)));
+    vm.remove_breakpoint(0x3000);
+    assert_eq!(vm.run_until_break(), Ok(StepResult::Breakpoint(0x3003)));
+    assert_eq!(vm.get_register(Register::R0), 2);
+}
+
+#[test]
+fn conditional_breakpoints_stop_only_when_their_predicate_holds() {
+    let (mut vm, _) = machine(&[
+        encode_add_imm(Register::R0, Register::R0, 1), // x3000 LOOP
+        encode_add_imm(Register::R1, Register::R0, -10), // x3001
+        encode_br(true, false, false, -3),             // x3002 BRn LOOP
+        HALT,
+    ]);
+    vm.add_conditional_breakpoint(0x3001, Box::new(|vm| vm.get_register(Register::R0) == 5));
+
+    assert_eq!(vm.run_until_break(), Ok(StepResult::Breakpoint(0x3001)));
+    assert_eq!(vm.get_register(Register::R0), 5);
+    assert_eq!(vm.run_until_break(), Ok(StepResult::Halted));
+    assert_eq!(vm.get_register(Register::R0), 10);
+}
+
+#[test]
+fn a_plain_breakpoint_replaces_a_conditional_one() {
+    let (mut vm, _) = machine(&[encode_add_imm(Register::R0, Register::R0, 1), HALT]);
+    vm.add_conditional_breakpoint(0x3001, Box::new(|_| false));
+    vm.add_breakpoint(0x3001);
+
+    assert_eq!(vm.run_until_break(), Ok(StepResult::Breakpoint(0x3001)));
+}
+
+#[test]
+fn big_and_little_endian_images_load_the_same_memory() {
+    let words = crate::programs::hello_world();
+    let big = image_bytes(0x3000, &words);
+    let little: Vec<u8> = big.chunks(2).flat_map(|pair| [pair[1], pair[0]]).collect();
+
+    let (mut from_big, _) = machine(&[]);
+    let (mut from_little, _) = machine(&[]);
+    assert_eq!(
+        from_big
+            .load_image_bytes_with_order(&big, ByteOrder::BigEndian)
+            .unwrap(),
+        0x3000
+    );
+    assert_eq!(
+        from_little
+            .load_image_bytes_with_order(&little, ByteOrder::LittleEndian)
+            .unwrap(),
+        0x3000
+    );
+
+    assert!(from_big.diff(&from_little).is_empty());
+    assert_eq!(from_big.peek(0x3000), words[0]);
+    assert_eq!(ByteOrder::default(), ByteOrder::BigEndian);
+}
+
+#[test]
+fn call_stack_follows_nested_calls_and_returns() {
+    let (mut vm, _) = machine(&[encode_jsr(0x0F), HALT]); // x3000 JSR A
+    vm.load_words(
+        0x3010,
+        &[
+            encode_st(Register::R7, 5), // x3010 A: ST R7, SAVE
+            encode_jsr(0x0E),           // x3011 JSR B
+            encode_ld(Register::R7, 3), // x3012 LD R7, SAVE
+            encode_ret(),               // x3013 RET
+        ],
+    );
+    vm.load_words(0x3020, &[encode_ret()]); // x3020 B: RET
+
+    vm.step_n(3).unwrap();
+    assert_eq!(vm.pc(), 0x3020);
+    assert_eq!(vm.call_stack(), [0x3001, 0x3012]);
+
+    vm.step().unwrap();
+    assert_eq!(vm.call_stack(), [0x3001]);
+
+    vm.step_n(2).unwrap();
+    assert_eq!(vm.pc(), 0x3001);
+    assert!(vm.call_stack().is_empty());
+}
+
+#[test]
+fn jsrr_and_vectored_traps_push_frames() {
+    let options = Options {
+        vectored_traps: true,
+        ..Options::default()
+    };
+    let (mut vm, _) = machine_with(options, &[encode_jsrr(Register::R1), 0xF030]);
+    vm.set_register(Register::R1, 0x4000);
+    vm.load_words(0x4000, &[encode_ret()]);
+    vm.load_words(0x0030, &[0x0400]);
+
+    vm.step().unwrap();
+    assert_eq!(vm.call_stack(), [0x3001]);
+    vm.step_n(2).unwrap();
+    assert_eq!(vm.call_stack(), [0x3002]);
+}
+
+#[test]
+fn run_timed_counts_the_instructions_executed() {
+    let add = encode_add_imm(Register::R0, Register::R0, 1);
+    let (mut vm, _) = machine(&[add, add, add, HALT]);
+
+    let (result, elapsed, cycles) = vm.run_timed();
+    assert_eq!(result, Ok(()));
+    assert_eq!(cycles, 4);
+    assert!(elapsed >= Duration::ZERO);
+
+    /* a second run only counts its own instructions */
+    vm.set_pc(0x3002);
+    let (_, _, cycles) = vm.run_timed();
+    assert_eq!(cycles, 2);
+}
+
+#[test]
+fn run_timed_returns_the_error_with_what_ran() {
+    let (mut vm, _) = machine(&[encode_add_imm(Register::R0, Register::R0, 1), 0xD000]);
+
+    let (result, _, cycles) = vm.run_timed();
+    assert!(result.is_err());
+    assert_eq!(cycles, 2);
+}
+
+#[test]
+fn in_prints_a_custom_prompt_once_and_echoes() {
+    for (newline_after_in, expected) in [(false, "key? xHALT\n"), (true, "key? x\nHALT\n")] {
+        let options = Options {
+            newline_after_in,
+            ..Options::default()
+        };
+        let (mut vm, output) = machine_with(options, &[0xF023, HALT]);
+        vm.set_in_prompt(Some(String::from("key? ")));
+        vm.push_key(b'x');
+
+        assert_eq!(vm.run(), Ok(u16::from(b'x')));
+        assert_eq!(printed(&output), expected);
+        assert_eq!(printed(&output).matches("key? ").count(), 1);
+    }
+}
+
+#[test]
+fn in_without_a_prompt_only_echoes() {
+    let (mut vm, output) = machine(&[0xF023, HALT]);
+    vm.set_in_prompt(None);
+    vm.push_key(b'y');
+
+    vm.run().unwrap();
+    assert_eq!(printed(&output), "yHALT\n");
+}
+
+#[test]
+fn run_fuzz_returns_for_arbitrary_bytes() {
+    let mut state = 0x5EED;
+    for len in (0..64).chain([255, 256, 1023, 4096]) {
+        let image: Vec<u8> = (0..len).map(|_| splitmix64(&mut state) as u8).collect();
+        let _ = run_fuzz(&image, 1_000);
+    }
+}
+
+#[test]
+fn run_fuzz_reports_what_the_program_did() {
+    assert_eq!(run_fuzz(&image_bytes(0x3000, &[HALT]), 10), Ok(()));
+    /* an infinite loop just runs out of cycles */
+    assert_eq!(run_fuzz(&image_bytes(0x3000, &[0x0FFF]), 10), Ok(()));
+    assert_eq!(
+        run_fuzz(&image_bytes(0x3000, &[0xF020]), 10),
+        Err(VmError::InputClosed)
+    );
+    /* nothing to run */
+    assert_eq!(run_fuzz(&[0x30], 10), Ok(()));
+}
+
+#[test]
+fn step_out_returns_to_the_instruction_after_the_call() {
+    let add = encode_add_imm(Register::R0, Register::R0, 1);
+    let (mut vm, _) = machine(&[encode_jsr(0x0F), HALT]); // x3000 JSR SUB
+    vm.load_words(0x3010, &[add, add, encode_ret()]); // x3010 SUB
+
+    vm.step().unwrap();
+    assert_eq!(vm.pc(), 0x3010);
+
+    assert_eq!(vm.step_out(), Ok(StepResult::ReachedTarget));
+    assert_eq!(vm.pc(), 0x3001);
+    assert_eq!(vm.get_register(Register::R0), 2);
+}
+
+#[test]
+fn step_out_stops_early_at_a_breakpoint() {
+    let add = encode_add_imm(Register::R0, Register::R0, 1);
+    let (mut vm, _) = machine(&[encode_jsr(0x0F), HALT]);
+    vm.load_words(0x3010, &[add, add, encode_ret()]);
+    vm.add_breakpoint(0x3011);
+
+    vm.step().unwrap();
+    assert_eq!(vm.step_out(), Ok(StepResult::Breakpoint(0x3011)));
+    assert_eq!(vm.step_out(), Ok(StepResult::ReachedTarget));
+    assert_eq!(vm.pc(), 0x3001);
+}
+
+#[test]
+fn small_memories_wrap_at_their_size() {
+    let options = Options {
+        memory_size: 0x1000,
+        ..Options::default()
+    };
+    let (mut vm, _) = machine_with(options, &[]);
+
+    vm.mem_write(0x0123, 0xAAAA).unwrap();
+    assert_eq!(vm.mem_read(0x1123), 0xAAAA);
+    assert_eq!(vm.peek(0xF123), 0xAAAA);
+
+    /* x3000 is cell x000 */
+    vm.mem_write(0x3000, 0xBBBB).unwrap();
+    assert_eq!(vm.peek(0x0000), 0xBBBB);
+}
+
+#[test]
+fn small_memories_still_see_device_registers() {
+    let options = Options {
+        memory_size: 0x1000,
+        ..Options::default()
+    };
+    let (mut vm, output) = machine_with(options, &[]);
+
+    assert_eq!(vm.mem_read(DSR), 0x8000);
+    vm.mem_write(DDR, u16::from(b'z')).unwrap();
+    assert_eq!(printed(&output), "z");
+    assert_eq!(vm.peek(0x0E06), 0);
+}
+
+#[test]
+#[should_panic(expected = "memory_size must be a power of two")]
+fn memory_size_must_be_a_power_of_two() {
+    VM::with_options(Options {
+        memory_size: 3000,
+        ..Options::default()
+    });
+}
+
+#[test]
+fn step_back_restores_registers_and_memory_exactly() {
+    let options = Options {
+        undo_depth: 16,
+        ..Options::default()
+    };
+    let (mut vm, _) = machine_with(
+        options,
+        &[
+            encode_add_imm(Register::R0, Register::R0, 5), // x3000
+            encode_st(Register::R0, 3),                    // x3001 ST R0, x3005
+            encode_not(Register::R1, Register::R0),        // x3002
+            encode_st(Register::R1, 1),                    // x3003 ST R1, x3005
+            HALT,                                          // x3004
+            0x1111,                                        // x3005
+        ],
+    );
+
+    vm.step_n(2).unwrap();
+    let earlier = vm.snapshot();
+
+    vm.step_n(2).unwrap();
+    assert_eq!(vm.peek(0x3005), 0xFFFA);
+
+    assert!(vm.step_back());
+    assert!(vm.step_back());
+    assert_eq!(vm.snapshot(), earlier);
+    assert_eq!(vm.peek(0x3005), 5);
+
+    assert!(vm.step_back());
+    assert!(vm.step_back());
+    assert_eq!(vm.peek(0x3005), 0x1111);
+    assert_eq!(vm.pc(), 0x3000);
+    assert!(!vm.step_back());
+}
+
+#[test]
+fn the_undo_journal_is_bounded() {
+    let options = Options {
+        undo_depth: 2,
+        ..Options::default()
+    };
+    let add = encode_add_imm(Register::R0, Register::R0, 1);
+    let (mut vm, _) = machine_with(options, &[add, add, add, add, HALT]);
+
+    vm.step_n(4).unwrap();
+    assert!(vm.step_back());
+    assert!(vm.step_back());
+    assert!(!vm.step_back());
+    assert_eq!(vm.get_register(Register::R0), 2);
+}
+
+#[test]
+fn step_back_is_a_no_op_without_a_journal() {
+    let (mut vm, _) = machine(&[encode_add_imm(Register::R0, Register::R0, 1)]);
+    vm.step().unwrap();
+
+    assert!(!vm.step_back());
+    assert_eq!(vm.get_register(Register::R0), 1);
+}
+
+/* counts the calls an output writer gets */
+#[derive(Clone, Default)]
+struct CallCounter(Rc<RefCell<(usize, usize)>>);
+
+impl Write for CallCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().0 += 1;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().1 += 1;
+        Ok(())
+    }
+}
+
+#[test]
+fn a_string_reaches_the_writer_in_one_write() {
+    let (mut vm, _) = machine(&crate::programs::hello_world());
+    let calls = CallCounter::default();
+    vm.set_output(calls.clone());
+
+    vm.run().unwrap();
+    /* PUTS and HALT: one write and one flush each, not one per character */
+    assert_eq!(*calls.0.borrow(), (2, 2));
+}
+
+/* a device that, when read, checks whether another thread can lock stdout */
+struct StdoutProbe(Rc<Cell<Option<bool>>>);
+
+impl MmioDevice for StdoutProbe {
+    fn read(&mut self) -> u16 {
+        let (sender, locked) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let _stdout = io::stdout().lock();
+            let _ = sender.send(());
+        });
+        let blocked = locked.recv_timeout(Duration::from_millis(200)).is_err();
+        self.0.set(Some(blocked));
+        0
+    }
+}
+
+#[test]
+fn a_run_holds_the_stdout_lock_until_it_returns() {
+    let blocked = Rc::new(Cell::new(None));
+    let mut vm = VM::new();
+    vm.set_input(LineInput::new(io::empty()));
+    vm.load_words(DEFAULT_ORIGIN, &[encode_ldi(Register::R0, 0), 0xFE10]); // LDI R0, PROBEP
+    vm.map_device(0xFE10, Box::new(StdoutProbe(blocked.clone())));
+
+    assert_eq!(vm.step_n(1), Ok(StepResult::Continue));
+    assert_eq!(blocked.get(), Some(true));
+    /* once the run returns, other threads get stdout again */
+    std::thread::spawn(|| drop(io::stdout().lock()))
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn a_push_loop_past_the_stack_region_overflows() {
+    let options = Options {
+        stack_bounds: Some((0x4000, 0x400F)),
+        ..Options::default()
+    };
+    let (mut vm, _) = machine_with(
+        options,
+        &[
+            encode_add_imm(Register::R6, Register::R6, -1), // x3000 PUSH: ADD R6, R6, #-1
+            encode_str(Register::R0, Register::R6, 0),      // x3001 STR R0, R6, #0
+            encode_br(true, true, true, -3),                // x3002 BRnzp PUSH
+        ],
+    );
+    vm.set_register(Register::R6, 0x4010);
+
+    assert_eq!(
+        vm.run(),
+        Err(VmError::StackOverflow {
+            pc: 0x3001,
+            address: 0x3FFF,
+        })
+    );
+    assert_eq!(vm.peek(0x4000), 0);
+    assert_eq!(vm.peek(0x3FFF), 0);
+}
+
+#[test]
+fn stack_bounds_only_check_r6_based_accesses() {
+    let options = Options {
+        stack_bounds: Some((0x4000, 0x400F)),
+        ..Options::default()
+    };
+    let (mut vm, _) = machine_with(
+        options,
+        &[
+            encode_str(Register::R0, Register::R5, 0), // x3000 STR R0, R5, #0
+            encode_ldr(Register::R1, Register::R6, 0), // x3001 LDR R1, R6, #0
+            encode_ldr(Register::R1, Register::R6, 1), // x3002 LDR R1, R6, #1
+        ],
+    );
+    vm.set_register(Register::R5, 0x5000);
+    vm.set_register(Register::R6, 0x400F);
+
+    assert_eq!(vm.step_n(2), Ok(StepResult::Continue));
+    assert_eq!(
+        vm.step(),
+        Err(VmError::StackOverflow {
+            pc: 0x3002,
+            address: 0x4010,
+        })
+    );
+}
+
+/* the number after `"key":` in flat JSON, as the dumps write it */
+fn json_number(json: &str, key: &str) -> u16 {
+    let start = json.find(&format!("\"{}\":", key)).unwrap() + key.len() + 3;
+    let digits: String = json[start..]
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+    digits.parse().unwrap()
+}
+
+#[test]
+fn state_json_holds_every_register_by_name() {
+    let (mut vm, _) = machine(&[]);
+    for (index, value) in [(0, 1), (3, 0xFFFF), (7, 0x3001)] {
+        vm.set_register(Register::try_from(index).unwrap(), value);
+    }
+    vm.set_pc(0x3456);
+
+    let json = vm.dump_state_json();
+    assert!(json.starts_with("{\"registers\":{") && json.ends_with("}}"));
+    for index in 0..8 {
+        let reg = Register::try_from(index).unwrap();
+        assert_eq!(
+            json_number(&json, &format!("R{}", index)),
+            vm.get_register(reg)
+        );
+    }
+    assert_eq!(json_number(&json, "PC"), 0x3456);
+    assert_eq!(json_number(&json, "COND"), ConditionFlag::Zro as u16);
+    assert!(!json.contains("memory"));
+}
+
+#[test]
+fn state_json_can_include_a_memory_region() {
+    let (mut vm, _) = machine(&[]);
+    vm.load_words(0x4000, &[10, 20, 65535]);
+
+    let json = vm.dump_state_json_with_memory(0x4000, 3);
+    assert_eq!(json_number(&json, "start"), 0x4000);
+    let words = &json[json.find("\"words\":[").unwrap() + 9..];
+    let words: Vec<u16> = words[..words.find(']').unwrap()]
+        .split(',')
+        .map(|word| word.parse().unwrap())
+        .collect();
+    assert_eq!(words, [10, 20, 65535]);
+}
+
+#[test]
+fn trap_hooks_return_their_value_in_r0() {
+    let (mut vm, _) = machine(&[0xF040, HALT]);
+    vm.on_trap(0x40, |[r0, r1, _]| r0.wrapping_mul(r1));
+    vm.set_register(Register::R0, 6);
+    vm.set_register(Register::R1, 7);
+
+    vm.step().unwrap();
+    assert_eq!(vm.get_register(Register::R7), 0x3001);
+    assert_eq!(vm.run(), Ok(42));
+}
+
+#[test]
+fn trap_hook_results_set_the_flags() {
+    let (mut vm, _) = machine(&[0xF040]);
+    vm.on_trap(0x40, |_| 0x8000);
+
+    vm.step().unwrap();
+    assert_eq!(vm.cond(), ConditionFlag::Neg as u16);
+}
+
+#[test]
+fn built_in_traps_cannot_be_hooked() {
+    let (mut vm, output) = machine(&[HALT]);
+    vm.on_trap(0x25, |_| 99);
+
+    assert_eq!(vm.run(), Ok(0));
+    assert_eq!(printed(&output), "HALT\n");
+}
+
+#[test]
+fn identically_seeded_rngs_read_the_same_sequence() {
+    let (mut first, _) = machine(&[]);
+    let (mut second, _) = machine(&[]);
+    first.seed_rng(1234);
+    second.seed_rng(1234);
+
+    let a: Vec<u16> = (0..16).map(|_| first.mem_read(RNG)).collect();
+    let b: Vec<u16> = (0..16).map(|_| second.mem_read(RNG)).collect();
+    assert_eq!(a, b);
+    /* every read advances the generator */
+    assert!(a.windows(2).any(|pair| pair[0] != pair[1]));
+
+    second.seed_rng(4321);
+    assert_ne!(second.mem_read(RNG), a[0]);
+}
+
+#[test]
+fn programs_read_the_rng_with_ldi() {
+    let (mut vm, _) = machine(&[encode_ldi(Register::R0, 1), HALT, RNG]);
+    vm.seed_rng(7);
+    let (mut reference, _) = machine(&[]);
+    reference.seed_rng(7);
+
+    assert_eq!(vm.run(), Ok(reference.mem_read(RNG)));
+}
+
+#[test]
+fn puts_runs_the_installed_os_routine() {
+    let options = Options {
+        track_coverage: true,
+        ..Options::default()
+    };
+    let (mut vm, output) = machine_with(options, &crate::programs::hello_world());
+    vm.install_default_os();
+
+    assert_eq!(vm.run(), Ok(0x3003));
+    assert_eq!(printed(&output), "Hello, World!\nHALT\n");
+    /* the PUTS routine executed, and it printed through the devices */
+    assert!(vm.executed_addresses().contains(0x020A));
+    assert!(vm.executed_addresses().contains(0x0207));
+}
+
+#[test]
+fn the_installed_os_reads_keys_and_reports_bad_traps() {
+    let (mut vm, output) = machine(&[0xF020, 0xF021, 0xF0FF]);
+    vm.install_default_os();
+    vm.push_key(b'Q');
+
+    vm.run().unwrap();
+    assert_eq!(printed(&output), "Qunknown trap\nHALT\n");
+}
+
+#[test]
+fn reserved_op_policy_decides_what_res_and_rti_do() {
+    for word in [0xD000, 0x8000] {
+        let program = [word, encode_add_imm(Register::R0, Register::R0, 1), HALT];
+
+        let (mut vm, _) = machine(&program);
+        assert!(vm.run().is_err(), "x{:04X} under Error", word);
+
+        let options = Options {
+            reserved_ops: ReservedOpPolicy::Nop,
+            ..Options::default()
+        };
+        let (mut vm, _) = machine_with(options, &program);
+        assert_eq!(vm.run(), Ok(1), "x{:04X} under Nop", word);
+
+        let options = Options {
+            reserved_ops: ReservedOpPolicy::Halt,
+            ..Options::default()
+        };
+        let (mut vm, output) = machine_with(options, &program);
+        assert_eq!(vm.run(), Ok(0), "x{:04X} under Halt", word);
+        assert_eq!(vm.pc(), 0x3001);
+        assert_eq!(printed(&output), "");
+    }
+}
+
+#[test]
+fn res_and_rti_fail_with_distinct_errors() {
+    let (mut vm, _) = machine(&[0xD123]);
+    assert_eq!(
+        vm.step(),
+        Err(VmError::Reserved {
+            pc: 0x3000,
+            instr: 0xD123,
+        })
+    );
+
+    let (mut vm, _) = machine(&[0x8000]);
+    assert_eq!(vm.step(), Err(VmError::UnimplementedRti { pc: 0x3000 }));
+}
+
+/* a keyboard that has nothing ready for the first `idle` polls */
+struct SlowKeyboard {
+    idle: usize,
+    keys: VecDeque<u8>,
+}
+
+impl Input for SlowKeyboard {
+    fn getchar(&mut self) -> Option<u8> {
+        self.keys.pop_front()
+    }
+
+    fn try_getchar(&mut self) -> Option<u8> {
+        if self.idle > 0 {
+            self.idle -= 1;
+            return None;
+        }
+        self.getchar()
+    }
+}
+
+#[test]
+fn kbsr_polls_proceed_while_no_key_is_ready() {
+    let (mut vm, _) = machine(&[
+        encode_add_imm(Register::R2, Register::R2, 1), // x3000 POLL: ADD R2, R2, #1
+        encode_ldi(Register::R1, 3),                   // x3001 LDI R1, KBSRP
+        encode_br(false, true, true, -3),              // x3002 BRzp POLL
+        encode_ldi(Register::R0, 2),                   // x3003 LDI R0, KBDRP
+        HALT,                                          // x3004
+        KBSR,                                          // x3005 KBSRP
+        KBDR,                                          // x3006 KBDRP
+    ]);
+    vm.set_input(SlowKeyboard {
+        idle: 3,
+        keys: VecDeque::from([b'g']),
+    });
+
+    assert_eq!(vm.run(), Ok(u16::from(b'g')));
+    /* three polls came back not ready before the fourth saw the key */
+    assert_eq!(vm.get_register(Register::R2), 4);
+}
+
+#[test]
+fn jumping_into_device_memory_faults_when_guarded() {
+    let options = Options {
+        guard_device_fetch: true,
+        ..Options::default()
+    };
+    let (mut vm, _) = machine_with(options, &[encode_jmp(Register::R1)]);
+    vm.set_register(Register::R1, KBSR);
+
+    assert_eq!(vm.step(), Ok(StepResult::Continue));
+    assert_eq!(vm.step(), Err(VmError::ExecuteDeviceMemory(KBSR)));
+
+    /* unguarded, the device register is read and executed */
+    let (mut vm, _) = machine(&[encode_jmp(Register::R1)]);
+    vm.set_register(Register::R1, KBSR);
+    vm.step_n(2).unwrap();
+    assert_eq!(vm.pc(), KBSR + 1);
+}
+
+#[test]
+fn standard_addresses_have_their_spec_values() {
+    assert_eq!(DEFAULT_ORIGIN, 0x3000);
+    assert_eq!(TRAP_VECTOR_BASE, 0x0000);
+    assert_eq!(KBSR, 0xFE00);
+    assert_eq!(KBDR, 0xFE02);
+    assert_eq!(DSR, 0xFE04);
+    assert_eq!(DDR, 0xFE06);
+    assert_eq!(MCR, 0xFFFE);
+    assert_eq!(MEMORY_MAX, 0x10000);
+}
+
+#[test]
+fn the_constants_name_the_registers_mem_read_and_mem_write_use() {
+    let (mut vm, output) = machine(&[]);
+    assert_eq!(vm.pc(), DEFAULT_ORIGIN);
+
+    assert_eq!(vm.mem_read(DSR), 0x8000);
+    vm.push_key(b'a');
+    assert_eq!(vm.mem_read(KBSR), 0x8000);
+    assert_eq!(vm.mem_read(KBDR), u16::from(b'a'));
+    vm.mem_write(DDR, u16::from(b'b')).unwrap();
+    assert_eq!(printed(&output), "b");
+}
+
+#[test]
+fn run_captured_reports_every_field() {
+    let report = run_captured(&[0xF020, 0xF021, HALT], b"z", 100);
+
+    assert_eq!(
+        report,
+        RunReport {
+            trace: vec![(0x3000, 0xF020), (0x3001, 0xF021), (0x3002, HALT)],
+            output: b"zHALT\n".to_vec(),
+            registers: [u16::from(b'z'), 0, 0, 0, 0, 0, 0, 0x3003],
+            pc: 0x3003,
+            cond: ConditionFlag::Pos as u16,
+            cycles: 3,
+            result: Ok(StepResult::Halted),
+        }
+    );
+}
+
+#[test]
+fn run_captured_stops_at_the_limit_or_an_error() {
+    let report = run_captured(&[0x0FFF], b"", 5);
+    assert_eq!(report.result, Ok(StepResult::LimitReached));
+    assert_eq!(report.cycles, 5);
+    assert_eq!(report.trace.len(), 5);
+
+    let report = run_captured(&[0xF020], b"", 5);
+    assert_eq!(report.result, Err(VmError::InputClosed));
+}
+
+#[test]
+fn registers_read_back_as_signed_values() {
+    let mut vm = VM::new();
+    vm.set_register(Register::R0, 0xFFFF);
+    vm.set_register(Register::R1, 0x8000);
+    vm.set_register(Register::R2, 0x7FFF);
+
+    assert_eq!(vm.reg_signed(Register::R0), -1);
+    assert_eq!(vm.reg_signed(Register::R1), -32768);
+    assert_eq!(vm.reg_signed(Register::R2), 32767);
+    assert_eq!(to_signed(0xFFF0), -16);
+}
+
+#[test]
+fn pause_on_input_returns_needs_input_without_reading_a_key() {
+    let options = Options {
+        pause_on_input: true,
+        ..Options::default()
+    };
+    let (mut vm, _) = machine_with(
+        options,
+        &[encode_add_imm(Register::R1, Register::R1, 1), 0xF020, HALT],
+    );
+    vm.set_input(LineInput::new(&b"k"[..]));
+
+    assert_eq!(vm.step(), Ok(StepResult::Continue));
+    assert_eq!(vm.step(), Ok(StepResult::NeedsInput));
+    assert_eq!(vm.step(), Ok(StepResult::NeedsInput));
+    assert_eq!(vm.pc(), 0x3001);
+    assert_eq!(vm.cycles(), 1);
+
+    /* the key is still there for run, which reads the input */
+    assert!(vm.run().is_ok());
+    assert_eq!(vm.get_register(Register::R0), u16::from(b'k'));
+}
+
+#[test]
+fn hex_text_loads_at_its_origin_and_runs() {
+    let (mut vm, output) = machine(&[]);
+    let text = "\
+; prints 'A' and halts
+.ORIG x4000
+\tE002 ; LEA R0, CHAR
+F022   ; PUTS
+
+0xF025 ; HALT
+0041   ; CHAR: 'A'
+0000
+.END
+FFFF   ; after .END, ignored
+";
+
+    assert_eq!(vm.load_hex_text(text).unwrap(), 0x4000);
+    assert_eq!(vm.peek(0x4004), 0x0000);
+    assert_eq!(vm.peek(0x4005), 0x0000);
+
+    vm.set_pc(0x4000);
+    assert!(vm.run().is_ok());
+    assert_eq!(printed(&output), "AHALT\n");
+}
+
+#[test]
+fn hex_text_errors_name_the_line() {
+    let mut vm = VM::new();
+
+    let message = |result: Result<u16, LoadError>| match result {
+        Err(LoadError::Parse { line, message }) => (line, message),
+        other => panic!("expected a parse error, got {:?}", other),
+    };
+
+    assert_eq!(
+        message(vm.load_hex_text(".ORIG x3000\n1234\nnope\n")),
+        (3, "expected one hex word".to_string())
+    );
+    assert_eq!(
+        message(vm.load_hex_text("1234\n.ORIG x3000\n")),
+        (2, ".ORIG must come once, before any word".to_string())
+    );
+    assert!(matches!(
+        vm.load_hex_text("; no origin\n.ORIG\n"),
+        Err(LoadError::Parse { line: 2, .. })
+    ));
+}
+
+#[test]
+fn hex_text_without_an_origin_loads_at_x3000() {
+    let mut vm = VM::new();
+    assert_eq!(vm.load_hex_text("F025\n").unwrap(), 0x3000);
+    assert_eq!(vm.peek(0x3000), HALT);
+}
+
+#[test]
+fn br_with_no_condition_bits_never_branches() {
+    for flag in [ConditionFlag::Neg, ConditionFlag::Zro, ConditionFlag::Pos] {
+        let (mut vm, _) = machine(&[encode_br(false, false, false, 5)]);
+        vm.set_register(Register::Cond, flag as u16);
+
+        assert_eq!(vm.step(), Ok(StepResult::Continue));
+        assert_eq!(vm.pc(), 0x3001, "{:?}", flag);
+    }
+}
+
+#[test]
+fn brnzp_always_branches() {
+    for flag in [ConditionFlag::Neg, ConditionFlag::Zro, ConditionFlag::Pos] {
+        let (mut vm, _) = machine(&[encode_br(true, true, true, 5)]);
+        vm.set_register(Register::Cond, flag as u16);
+
+        assert_eq!(vm.step(), Ok(StepResult::Continue));
+        assert_eq!(vm.pc(), 0x3006, "{:?}", flag);
+    }
+}
+
+#[test]
+fn fill_sets_a_range_and_wraps_past_xffff() {
+    let mut vm = VM::new();
+    vm.fill(0x4000, 0x10, 0xBEEF);
+
+    assert_eq!(vm.peek(0x3FFF), 0);
+    assert_eq!(vm.peek(0x4000), 0xBEEF);
+    assert_eq!(vm.peek(0x4008), 0xBEEF);
+    assert_eq!(vm.peek(0x400F), 0xBEEF);
+    assert_eq!(vm.peek(0x4010), 0);
+
+    vm.fill(0xFFFE, 4, 0x1111);
+    assert_eq!(vm.memory()[0xFFFE], 0x1111);
+    assert_eq!(vm.memory()[0xFFFF], 0x1111);
+    assert_eq!(vm.memory()[0x0000], 0x1111);
+    assert_eq!(vm.memory()[0x0001], 0x1111);
+    assert_eq!(vm.memory()[0x0002], 0);
+
+    vm.fill(0x5000, 0, 0x2222);
+    assert_eq!(vm.peek(0x5000), 0);
+}
+
+#[test]
+fn a_jmp_through_a_zeroed_register_is_caught_when_asked() {
+    let jmp_r3 = 0xC0C0; // JMP R3, never set
+    let options = Options {
+        catch_jump_to_zero: true,
+        ..Options::default()
+    };
+    let (mut vm, _) = machine_with(options, &[jmp_r3]);
+    assert_eq!(vm.step(), Err(VmError::JumpToZero { pc: 0x3000 }));
+
+    /* off by default */
+    let (mut vm, _) = machine(&[jmp_r3]);
+    assert_eq!(vm.step(), Ok(StepResult::Continue));
+    assert_eq!(vm.pc(), 0x0000);
+}
+
+#[test]
+fn code_below_user_space_may_jump_to_zero() {
+    let options = Options {
+        catch_jump_to_zero: true,
+        ..Options::default()
+    };
+    let (mut vm, _) = machine_with(options, &[]);
+    vm.load_words(0x0200, &[0xC0C0]);
+    vm.set_pc(0x0200);
+
+    assert_eq!(vm.step(), Ok(StepResult::Continue));
+    assert_eq!(vm.pc(), 0x0000);
+}
+
+/* LEA R0, STR; PUTS; HALT; STR: "Hi" */
+const PUTS_HI: [u16; 6] = [0xE002, 0xF022, HALT, 0x0048, 0x0069, 0x0000];
+
+#[test]
+fn run_until_output_hands_back_each_chunk() {
+    let (mut vm, output) = machine(&PUTS_HI);
+
+    let mut states = Vec::new();
+    loop {
+        let state = vm.run_until_output();
+        let done = !matches!(state, RunState::Output(_));
+        states.push(state);
+        if done {
+            break;
+        }
+    }
+
+    assert_eq!(
+        states,
+        [
+            RunState::Output(b"Hi".to_vec()),
+            RunState::Output(b"HALT\n".to_vec()),
+            RunState::Halted,
+        ]
+    );
+    assert_eq!(printed(&output), "");
+}
+
+#[test]
+fn reset_and_restore_forget_an_unreported_halt() {
+    let (mut vm, _) = machine(&PUTS_HI);
+    let start = vm.snapshot();
+
+    vm.run_until_output();
+    assert_eq!(vm.run_until_output(), RunState::Output(b"HALT\n".to_vec()));
+    vm.reset();
+    assert_eq!(vm.run_until_output(), RunState::Output(b"Hi".to_vec()));
+
+    vm.run_until_output();
+    vm.restore(&start);
+    assert_eq!(vm.run_until_output(), RunState::Output(b"Hi".to_vec()));
+}
+
+/* LEA R0, STR; PUTSP; HALT; then the packed string */
+fn putsp(packed: &[u16]) -> String {
+    let mut words = vec![0xE002, 0xF024, HALT];
+    words.extend_from_slice(packed);
+    let (mut vm, output) = machine(&words);
+    assert!(vm.run().is_ok());
+    printed(&output)
+}
+
+#[test]
+fn putsp_prints_both_bytes_of_every_word_low_first() {
+    // "ABCD": both halves of each word used, then a zero word
+    assert_eq!(putsp(&[0x4241, 0x4443, 0x0000, 0x4545]), "ABCDHALT\n");
+}
+
+#[test]
+fn putsp_stops_at_a_zero_high_byte() {
+    // "ABC": the last word's high byte is the terminator
+    assert_eq!(putsp(&[0x4241, 0x0043, 0x4545]), "ABCHALT\n");
+}
+
+#[test]
+fn putsp_of_an_empty_string_prints_nothing() {
+    assert_eq!(putsp(&[0x0000, 0x4545]), "HALT\n");
+    // a zero low byte ends the string even if the high byte is not zero
+    assert_eq!(putsp(&[0x4500]), "HALT\n");
+}
+
+/* counts up on every read; a write sets the count */
+struct Counter(u16);
+
+impl MmioDevice for Counter {
+    fn read(&mut self) -> u16 {
+        self.0 += 1;
+        self.0
+    }
+
+    fn write(&mut self, value: u16) {
+        self.0 = value;
+    }
+}
+
+#[test]
+fn a_mapped_device_answers_reads_and_writes() {
+    let (mut vm, _) = machine(&[
+        encode_ldi(Register::R0, 2), // x3000 LDI R0, COUNTERP
+        encode_ldi(Register::R1, 1), // x3001 LDI R1, COUNTERP
+        encode_sti(Register::R2, 0), // x3002 STI R2, COUNTERP
+        0xFE10,                      // x3003 COUNTERP
+    ]);
+    vm.map_device(0xFE10, Box::new(Counter(0)));
+    vm.set_register(Register::R2, 100);
+
+    for _ in 0..3 {
+        vm.step().unwrap();
+    }
+    assert_eq!(vm.get_register(Register::R0), 1);
+    assert_eq!(vm.get_register(Register::R1), 2);
+    assert_eq!(vm.mem_read(0xFE10), 101);
+    assert_eq!(vm.mem_read(0xFE10), 102);
+    /* the device's address never held the stored value */
+    assert_eq!(vm.memory()[0xFE10], 0);
+}
+
+#[test]
+fn a_register_watch_fires_only_when_that_register_changes() {
+    let (mut vm, _) = machine(&[
+        encode_add_imm(Register::R1, Register::R1, 3), // x3000 ADD R1, R1, #3
+        encode_add_imm(Register::R0, Register::R0, 5), // x3001 ADD R0, R0, #5
+        encode_add_imm(Register::R0, Register::R0, 0), // x3002 ADD R0, R0, #0
+        HALT,
+    ]);
+    vm.add_reg_watch(Register::R0);
+
+    assert_eq!(vm.step(), Ok(StepResult::Continue));
+    assert_eq!(
+        vm.step(),
+        Ok(StepResult::RegChanged {
+            reg: Register::R0,
+            old: 0,
+            new: 5,
+        })
+    );
+    /* written, but to the value it already had */
+    assert_eq!(vm.step(), Ok(StepResult::Continue));
+}
+
+fn wrapping_from_xfffe(options: Options, last: u16) -> (VM, Result<StepResult, VmError>) {
+    let (mut vm, _) = machine_with(options, &[]);
+    vm.load_words(0xFFFE, &[0x1020, last]); // ADD R0, R0, #0; then `last`
+    vm.set_pc(0xFFFE);
+    assert_eq!(vm.step(), Ok(StepResult::Continue));
+    let result = vm.step();
+    (vm, result)
+}
+
+#[test]
+fn running_sequentially_off_the_end_of_memory_is_caught_when_asked() {
+    let catching = Options {
+        catch_pc_wrap: true,
+        ..Options::default()
+    };
+    let (_, result) = wrapping_from_xfffe(catching.clone(), 0x1020);
+    assert_eq!(result, Err(VmError::PcWrapped));
+
+    /* a zero word is BR with no condition bits: a NOP that falls through */
+    let (_, result) = wrapping_from_xfffe(catching.clone(), 0x0000);
+    assert_eq!(result, Err(VmError::PcWrapped));
+
+    /* a branch to x0000 is a deliberate transfer, not a wrap */
+    let (vm, result) = wrapping_from_xfffe(catching, encode_br(true, true, true, 0));
+    assert_eq!(result, Ok(StepResult::Continue));
+    assert_eq!(vm.pc(), 0x0000);
+
+    /* off by default */
+    let (vm, result) = wrapping_from_xfffe(Options::default(), 0x1020);
+    assert_eq!(result, Ok(StepResult::Continue));
+    assert_eq!(vm.pc(), 0x0000);
+}
+
+fn hash_of(snapshot: &VmSnapshot) -> u64 {
+    use std::hash::{BuildHasher, RandomState};
+    thread_local!(static STATE: RandomState = RandomState::new());
+    STATE.with(|state| state.hash_one(snapshot))
+}
+
+#[test]
+fn equal_snapshots_compare_and_hash_equal() {
+    let (mut vm, _) = machine(&[encode_add_imm(Register::R0, Register::R0, 1)]);
+    let first = vm.snapshot();
+    let second = vm.snapshot();
+    assert_eq!(first, second);
+    assert_eq!(hash_of(&first), hash_of(&second));
+
+    vm.step().unwrap();
+    let stepped = vm.snapshot();
+    assert_ne!(first, stepped);
+    assert_ne!(hash_of(&first), hash_of(&stepped));
+
+    /* one memory word is enough to tell states apart */
+    vm.restore(&first);
+    assert_eq!(vm.snapshot(), first);
+    vm.load_words(0x8000, &[1]);
+    assert_ne!(vm.snapshot(), first);
+    assert_ne!(hash_of(&vm.snapshot()), hash_of(&first));
+}
+
+#[test]
+fn snapshots_spot_a_revisited_state() {
+    // NOT R0 in a loop: four steps put R0, the PC and the flags back
+    let (mut vm, _) = machine(&[
+        encode_not(Register::R0, Register::R0),
+        encode_br(true, true, true, -2),
+    ]);
+    let mut seen = std::collections::HashSet::new();
+
+    let revisited = (0..10).find(|_| {
+        let fresh = seen.insert(vm.snapshot());
+        vm.step().unwrap();
+        !fresh
+    });
+    assert_eq!(revisited, Some(4));
+}
+
+#[test]
+fn the_recent_trace_shows_how_a_fault_was_reached() {
+    let options = Options {
+        recent_depth: 3,
+        ..Options::default()
+    };
+    let (mut vm, _) = machine_with(
+        options,
+        &[
+            encode_add_imm(Register::R0, Register::R0, 1), // x3000
+            encode_add_imm(Register::R0, Register::R0, 2), // x3001
+            encode_br(true, true, true, 1),                // x3002 BRnzp x3004
+            HALT,                                          // x3003
+            0xD000,                                        // x3004 reserved
+        ],
+    );
+
+    assert_eq!(
+        vm.run(),
+        Err(VmError::Reserved {
+            pc: 0x3004,
+            instr: 0xD000
+        })
+    );
+    /* bounded to the last three, the failing instruction last */
+    assert_eq!(
+        vm.recent_trace(),
+        [(0x3001, 0x1022), (0x3002, 0x0E01), (0x3004, 0xD000)]
+    );
+
+    vm.reset();
+    assert!(vm.recent_trace().is_empty());
+}
+
+#[test]
+fn the_recent_trace_is_on_by_default_and_can_be_turned_off() {
+    let (mut vm, _) = machine(&[0xD000]);
+    assert!(vm.run().is_err());
+    assert_eq!(vm.recent_trace(), [(0x3000, 0xD000)]);
+
+    let options = Options {
+        recent_depth: 0,
+        ..Options::default()
+    };
+    let (mut vm, _) = machine_with(options, &[0xD000]);
+    assert!(vm.run().is_err());
+    assert!(vm.recent_trace().is_empty());
+}
+
+#[test]
+fn a_layered_run_starts_in_the_os_and_reaches_the_user_program() {
+    let (mut vm, output) = machine(&[
+        encode_add_imm(Register::R0, Register::R1, 1), // x3000 ADD R0, R1, #1
+        HALT,                                          // x3001
+    ]);
+    /* the OS: set R1, then jump to the user program */
+    vm.load_words(
+        0x0200,
+        &[
+            encode_and_imm(Register::R1, Register::R1, 0), // x0200
+            encode_add_imm(Register::R1, Register::R1, 7), // x0201
+            encode_ld(Register::R2, 1),                    // x0202 LD R2, USERP
+            encode_jmp(Register::R2),                      // x0203
+            DEFAULT_ORIGIN,                                // x0204 USERP
+        ],
+    );
+    /* its HALT routine clears the MCR clock bit */
+    vm.load_words(
+        0x0210,
+        &[
+            encode_and_imm(Register::R3, Register::R3, 0), // x0210
+            encode_sti(Register::R3, 0),                   // x0211 STI R3, MCRP
+            MCR,                                           // x0212 MCRP
+        ],
+    );
+    vm.load_words(TRAP_VECTOR_BASE + 0x25, &[0x0210]);
+
+    assert_eq!(vm.run_layered(0x0200), Ok(8));
+    assert_eq!(vm.get_register(Register::R1), 7);
+    assert_eq!(vm.get_register(Register::R7), 0x3002);
+    assert_eq!(vm.pc(), 0x0212);
+    /* the OS handled HALT, so the built-in message never printed */
+    assert_eq!(printed(&output), "");
+}
+
+#[test]
+fn the_write_quota_stops_a_store_loop_at_the_limit() {
+    let options = Options {
+        write_quota: Some(3),
+        ..Options::default()
+    };
+    let (mut vm, _) = machine_with(
+        options,
+        &[
+            encode_str(Register::R0, Register::R1, 0), // x3000 LOOP: STR R0, R1, #0
+            encode_add_imm(Register::R1, Register::R1, 1), // x3001 ADD R1, R1, #1
+            encode_br(true, true, true, -3),           // x3002 BRnzp LOOP
+        ],
+    );
+    vm.set_register(Register::R0, 5);
+    vm.set_register(Register::R1, 0x4000);
+
+    assert_eq!(vm.run(), Err(VmError::WriteQuotaExceeded(0x4003)));
+    assert_eq!(&vm.memory()[0x4000..0x4004], [5, 5, 5, 0]);
+    assert_eq!(vm.cycles(), 10);
+
+    /* loading does not count, and a reset starts the count again */
+    vm.load_words(0x5000, &[1, 2, 3, 4]);
+    vm.reset();
+    assert_eq!(vm.mem_write(0x4100, 1), Ok(()));
+    assert_eq!(vm.mem_write(0x4101, 1), Ok(()));
+    assert_eq!(vm.mem_write(0x4102, 1), Ok(()));
+    assert_eq!(
+        vm.mem_write(0x4103, 1),
+        Err(VmError::WriteQuotaExceeded(0x4103))
+    );
+}
+
+#[test]
+fn snapshots_round_trip_through_a_file_and_remember_a_halt() {
+    let (mut vm, _) = machine(&[encode_add_imm(Register::R3, Register::R3, 9), HALT]);
+    vm.step().unwrap();
+    let running = vm.snapshot();
+    assert!(!running.halted());
+
+    let mut bytes = Vec::new();
+    running.write_to(&mut bytes).unwrap();
+    assert!(bytes.starts_with(b"LC3S"));
+    assert_eq!(VmSnapshot::read_from(&bytes[..]).unwrap(), running);
+
+    assert_eq!(vm.step(), Ok(StepResult::Halted));
+    let halted = vm.snapshot();
+    assert!(halted.halted());
+    let mut bytes = Vec::new();
+    halted.write_to(&mut bytes).unwrap();
+    assert!(bytes.starts_with(b"LC3H"));
+    let read = VmSnapshot::read_from(&bytes[..]).unwrap();
+    assert!(read.halted());
+    assert_eq!(read, halted);
+
+    /* restoring carries the flag, and a reset clears it */
+    let (mut other, _) = machine(&[]);
+    other.restore(&read);
+    assert!(other.snapshot().halted());
+    other.reset();
+    assert!(!other.snapshot().halted());
+}
+
+#[test]
+fn a_cost_model_weights_opcodes_and_memory_accesses() {
+    let program = [
+        encode_add_imm(Register::R0, Register::R0, 1), // x3000
+        encode_ld(Register::R1, 3),                    // x3001 LD R1, PTR
+        encode_ldi(Register::R2, 2),                   // x3002 LDI R2, PTR
+        encode_str(Register::R0, Register::R1, 0),     // x3003
+        HALT,                                          // x3004
+        0x4000,                                        // x3005 PTR
+    ];
+
+    let mut costs = CostModel::default();
+    costs.per_op[OpCode::Ld as usize] = 2;
+    costs.per_op[OpCode::Trap as usize] = 5;
+    costs.per_access = 3;
+    let options = Options {
+        costs,
+        ..Options::default()
+    };
+    let (mut vm, _) = machine_with(options, &program);
+    vm.run().unwrap();
+
+    // ADD 1, LD 2 + 3, LDI 1 + 2 * 3, STR 1 + 3, HALT 5
+    assert_eq!(vm.weighted_cycles(), 22);
+    assert_eq!(vm.cycles(), 5);
+
+    /* by default the two counters agree */
+    let (mut vm, _) = machine(&program);
+    vm.run().unwrap();
+    assert_eq!(vm.weighted_cycles(), vm.cycles());
+}
+
+#[test]
+fn stepping_over_a_trap_reports_it_and_what_it_printed() {
+    let (mut vm, output) = machine(&PUTS_HI);
+
+    assert_eq!(vm.step_over_trap(), Ok(StepResult::Continue));
+    assert_eq!(
+        vm.step_over_trap(),
+        Ok(StepResult::Trapped {
+            vector: 0x22,
+            output: 2,
+        })
+    );
+    assert_eq!(printed(&output), "Hi");
+    assert_eq!(vm.step_over_trap(), Ok(StepResult::Halted));
+}
+
+#[test]
+fn stepping_over_a_vectored_trap_runs_the_whole_routine() {
+    let (mut vm, output) = machine(&PUTS_HI);
+    vm.install_default_os();
+
+    vm.step_over_trap().unwrap();
+    let cycles = vm.cycles();
+    assert_eq!(
+        vm.step_over_trap(),
+        Ok(StepResult::Trapped {
+            vector: 0x22,
+            output: 2,
+        })
+    );
+    assert_eq!(vm.pc(), 0x3002);
+    assert!(vm.cycles() - cycles > 1, "the OS routine ran as one step");
+    assert_eq!(printed(&output), "Hi");
+}
+
+fn general_registers(vm: &VM) -> Vec<u16> {
+    crate::instruction::GENERAL_REGISTERS
+        .iter()
+        .map(|&reg| vm.get_register(reg))
+        .collect()
+}
+
+#[test]
+fn a_register_seed_fills_r0_to_r7_reproducibly() {
+    let options = Options {
+        register_seed: Some(42),
+        ..Options::default()
+    };
+    let mut vm = VM::with_options(options.clone());
+    let seeded = [
+        0x6E95, 0xF103, 0x9F52, 0xE394, 0x23F2, 0xDB06, 0x6D5D, 0x2FA4,
+    ];
+    assert_eq!(general_registers(&vm), seeded);
+    assert_eq!(vm.pc(), DEFAULT_ORIGIN);
+    assert_eq!(vm.cond(), ConditionFlag::Zro as u16);
+
+    /* the same values again after a reset and in another VM */
+    vm.set_register(Register::R0, 0);
+    vm.reset();
+    assert_eq!(general_registers(&vm), seeded);
+    assert_eq!(general_registers(&VM::with_options(options)), seeded);
+
+    let other = VM::with_options(Options {
+        register_seed: Some(43),
+        ..Options::default()
+    });
+    assert_ne!(general_registers(&other), seeded);
+}
+
+#[test]
+fn registers_start_at_zero_by_default() {
+    assert_eq!(general_registers(&VM::new()), [0; 8]);
+}
+
+/* x0002 + 1 - 16: PC-relative operands at x0002 with this offset wrap to xFFF3 */
+const BELOW_ZERO: i16 = -16;
+const WRAPPED: u16 = 0xFFF3;
+
+/* runs `instr` alone at x0002 */
+fn run_at_x0002(instr: u16, setup: impl FnOnce(&mut VM)) -> VM {
+    let (mut vm, _) = machine(&[]);
+    vm.load_words(0x0002, &[instr]);
+    vm.set_pc(0x0002);
+    setup(&mut vm);
+    assert_eq!(vm.step(), Ok(StepResult::Continue));
+    vm
+}
+
+#[test]
+fn lea_wraps_below_x0000() {
+    let vm = run_at_x0002(encode_lea(Register::R0, BELOW_ZERO), |_| {});
+    assert_eq!(vm.get_register(Register::R0), WRAPPED);
+}
+
+#[test]
+fn ld_wraps_below_x0000() {
+    let vm = run_at_x0002(encode_ld(Register::R0, BELOW_ZERO), |vm| {
+        vm.load_words(WRAPPED, &[0x1234]);
+    });
+    assert_eq!(vm.get_register(Register::R0), 0x1234);
+}
+
+#[test]
+fn ldi_wraps_below_x0000() {
+    let vm = run_at_x0002(encode_ldi(Register::R0, BELOW_ZERO), |vm| {
+        vm.load_words(WRAPPED, &[0x4000]);
+        vm.load_words(0x4000, &[0x5678]);
+    });
+    assert_eq!(vm.get_register(Register::R0), 0x5678);
+}
+
+#[test]
+fn br_wraps_below_x0000() {
+    let vm = run_at_x0002(encode_br(true, true, true, BELOW_ZERO), |_| {});
+    assert_eq!(vm.pc(), WRAPPED);
+}
+
+#[test]
+fn st_wraps_below_x0000() {
+    let vm = run_at_x0002(encode_st(Register::R3, BELOW_ZERO), |vm| {
+        vm.set_register(Register::R3, 0xABCD);
+    });
+    assert_eq!(vm.peek(WRAPPED), 0xABCD);
+}
+
+#[test]
+fn sti_wraps_below_x0000() {
+    let vm = run_at_x0002(encode_sti(Register::R3, BELOW_ZERO), |vm| {
+        vm.set_register(Register::R3, 0xABCD);
+        vm.load_words(WRAPPED, &[0x4000]);
+    });
+    assert_eq!(vm.peek(0x4000), 0xABCD);
+    assert_eq!(vm.peek(WRAPPED), 0x4000);
+}
+
+#[test]
+fn the_access_hook_sees_fetches_loads_and_stores_in_order() {
+    let (mut vm, _) = machine(&[
+        encode_ldr(Register::R0, Register::R1, 2), // x3000 LDR R0, R1, #2
+        encode_str(Register::R0, Register::R1, 3), // x3001 STR R0, R1, #3
+    ]);
+    vm.set_register(Register::R1, 0x4000);
+    vm.load_words(0x4002, &[0x0077]);
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let sink = Rc::clone(&log);
+    vm.on_mem_access(move |access| sink.borrow_mut().push(*access));
+    vm.step().unwrap();
+    vm.step().unwrap();
+
+    let access = |kind, address, value| Access {
+        kind,
+        address,
+        value,
+    };
+    assert_eq!(
+        *log.borrow(),
+        [
+            access(AccessKind::Fetch, 0x3000, 0x6042),
+            access(AccessKind::Read, 0x4002, 0x0077),
+            access(AccessKind::Fetch, 0x3001, 0x7043),
+            access(AccessKind::Write, 0x4003, 0x0077),
+        ]
+    );
+
+    /* and nothing once the hook is removed */
+    vm.clear_mem_access_hook();
+    vm.set_pc(0x3000);
+    vm.step().unwrap();
+    assert_eq!(log.borrow().len(), 4);
+}
+
+#[test]
+fn instructions_set_the_flag_condition_for_gives() {
+    for value in [0u16, 1, 0x7FFF, 0x8000, 0xFFFF] {
+        let (mut vm, _) = machine(&[encode_ld(Register::R0, 0), value]);
+        vm.step().unwrap();
+        assert_eq!(vm.cond(), condition_for(value) as u16, "x{:04X}", value);
+    }
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn gzip_images_load_like_the_uncompressed_ones() {
+    use flate2::{write::GzEncoder, Compression};
+
+    let bytes = image_bytes(0x3000, &crate::programs::hello_world());
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&bytes).unwrap();
+    let packed = encoder.finish().unwrap();
+    assert_ne!(packed, bytes);
+
+    let mut plain = VM::new();
+    assert_eq!(plain.read_image_from(&bytes[..]).unwrap(), 0x3000);
+    let mut unpacked = VM::new();
+    assert_eq!(unpacked.read_image_from(&packed[..]).unwrap(), 0x3000);
+    assert_eq!(unpacked.memory(), plain.memory());
+
+    /* a corrupt stream is an error, not a raw image */
+    let mut vm = VM::new();
+    assert!(vm.read_image_from(&packed[..packed.len() / 2]).is_err());
+}
+
+#[test]
+fn falling_into_string_data_is_diagnosed() {
+    // LEA R0, MSG; PUTS; no HALT; MSG: "Hello"
+    let mut program = vec![0xE001, 0xF022];
+    program.extend(crate::programs::stringz("Hello"));
+    let options = Options {
+        data_execution_limit: Some(4),
+        ..Options::default()
+    };
+    let (mut vm, output) = machine_with(options, &program);
+
+    assert_eq!(
+        vm.run(),
+        Err(VmError::SuspectedDataExecution { pc: 0x3002 })
+    );
+    assert_eq!(printed(&output), "Hello");
+    /* LEA, PUTS and three data words ran; the fourth was refused */
+    assert_eq!(vm.cycles(), 5);
+}
+
+#[test]
+fn a_short_run_of_nops_is_not_data() {
+    let options = Options {
+        data_execution_limit: Some(4),
+        ..Options::default()
+    };
+    let (mut vm, _) = machine_with(
+        options,
+        &[
+            0x0000,
+            0x0000,
+            0x0000,
+            encode_add_imm(Register::R0, Register::R0, 1),
+            0x0000,
+            HALT,
+        ],
+    );
+    assert_eq!(vm.run(), Ok(1));
+}
+
+#[test]
+#[should_panic(expected = "data_execution_limit must be at least 1")]
+fn a_data_execution_limit_of_zero_is_rejected() {
+    VM::with_options(Options {
+        data_execution_limit: Some(0),
+        ..Options::default()
+    });
+}
+
+#[test]
+fn expect_halt_returns_the_cycle_count_of_a_halting_program() {
+    let (mut vm, _) = machine(&[
+        encode_add_imm(Register::R0, Register::R0, 3),  // x3000
+        encode_add_imm(Register::R0, Register::R0, -1), // x3001 LOOP
+        encode_br(false, false, true, -2),              // x3002 BRp LOOP
+        HALT,                                           // x3003
+    ]);
+    assert_eq!(vm.expect_halt(100), Ok(8));
+
+    /* exactly enough is enough */
+    vm.reset();
+    assert_eq!(vm.expect_halt(8), Ok(8));
+    vm.reset();
+    assert_eq!(vm.expect_halt(7), Err(VmError::CycleLimitExceeded(7)));
+}
+
+#[test]
+fn expect_halt_fails_on_a_loop_or_a_fault() {
+    let (mut vm, _) = machine(&[encode_br(true, true, true, -1)]);
+    assert_eq!(vm.expect_halt(50), Err(VmError::CycleLimitExceeded(50)));
+    assert_eq!(vm.cycles(), 50);
+
+    let (mut vm, _) = machine(&[0xD000]);
+    assert!(matches!(
+        vm.expect_halt(50),
+        Err(VmError::Reserved { pc: 0x3000, .. })
+    ));
+}
+
+#[test]
+fn a_fault_handler_can_skip_an_illegal_word() {
+    let (mut vm, output) = machine(&[
+        0xD123,                                        // x3000 reserved
+        encode_add_imm(Register::R0, Register::R0, 4), // x3001
+        HALT,                                          // x3002
+    ]);
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let log = Rc::clone(&seen);
+    vm.on_fault(move |vm, word| {
+        log.borrow_mut().push((vm.pc(), word));
+        FaultAction::Skip
+    });
+
+    assert_eq!(vm.run(), Ok(4));
+    assert_eq!(*seen.borrow(), [(0x3001, 0xD123)]);
+    assert_eq!(printed(&output), "HALT\n");
+}
+
+#[test]
+fn a_fault_handler_can_abort_or_patch_and_retry() {
+    let (mut vm, _) = machine(&[0xD000, HALT]);
+    vm.on_fault(|_, _| FaultAction::Abort);
+    assert_eq!(
+        vm.run(),
+        Err(VmError::Reserved {
+            pc: 0x3000,
+            instr: 0xD000
+        })
+    );
+
+    /* emulate an extension: replace the word with ADD R0, R0, #7 */
+    let (mut vm, _) = machine(&[0xD000, HALT]);
+    vm.on_fault(|vm, _| {
+        vm.load_words(0x3000, &[encode_add_imm(Register::R0, Register::R0, 7)]);
+        FaultAction::Retry
+    });
+    assert_eq!(vm.run(), Ok(7));
+}
+
+#[test]
+fn set_cond_drives_the_next_branch() {
+    let brn = encode_br(true, false, false, 3);
+
+    let (mut vm, _) = machine(&[brn]);
+    vm.set_cond(ConditionFlag::Neg);
+    vm.step().unwrap();
+    assert_eq!(vm.pc(), 0x3004);
+
+    let (mut vm, _) = machine(&[brn]);
+    vm.set_cond(ConditionFlag::Pos);
+    vm.step().unwrap();
+    assert_eq!(vm.pc(), 0x3001);
+}
+
+#[test]
+fn set_cond_raw_allows_states_no_instruction_makes() {
+    let (mut vm, _) = machine(&[
+        encode_br(false, true, false, 3),
+        encode_br(false, false, true, 3),
+    ]);
+    vm.set_cond_raw(0b101);
+    assert_eq!(vm.cond(), 0b101);
+
+    vm.step().unwrap();
+    assert_eq!(vm.pc(), 0x3001, "BRz not taken with only N and P set");
+    vm.step().unwrap();
+    assert_eq!(vm.pc(), 0x3005, "BRp taken");
+
+    vm.set_cond_raw(0xFFF8);
+    assert_eq!(vm.cond(), 0);
+}
+
+#[test]
+fn read_image_tells_a_directory_from_a_missing_file() {
+    let mut vm = VM::new();
+    let directory = std::env::temp_dir();
+
+    assert!(matches!(
+        vm.read_image(&directory.to_string_lossy()),
+        Err(LoadError::IsDirectory)
+    ));
+    assert!(matches!(
+        vm.read_image(&temp_path("missing.obj")),
+        Err(LoadError::NotFound)
+    ));
+    assert_eq!(LoadError::IsDirectory.to_string(), "is a directory");
+    assert_eq!(LoadError::NotFound.to_string(), "file not found");
+}
+
+#[test]
+fn from_words_builds_a_ready_to_run_vm() {
+    // at x4000: LEA R0, MSG; PUTS; HALT; MSG: "ok"
+    let mut program = vec![0xE002, 0xF022, HALT];
+    program.extend(crate::programs::stringz("ok"));
+    let mut vm = VM::from_words(0x4000, &program);
+    let output = SharedBuffer::default();
+    vm.set_output(output.clone());
+
+    assert_eq!(vm.pc(), 0x4000);
+    assert_eq!(vm.peek(0x4003), u16::from(b'o'));
+    assert!(vm.run().is_ok());
+    assert_eq!(printed(&output), "okHALT\n");
+}
+
+#[test]
+fn continue_past_halt_runs_back_to_back_programs() {
+    let options = Options {
+        continue_past_halt: vec![0x3010],
+        ..Options::default()
+    };
+    let (mut vm, output) = machine_with(
+        options,
+        &[
+            encode_add_imm(Register::R0, Register::R0, 1), // x3000 first program
+            HALT,                                          // x3001
+        ],
+    );
+    vm.load_words(
+        0x3010,
+        &[
+            encode_add_imm(Register::R0, Register::R0, 5), // x3010 second program
+            encode_add_imm(Register::R1, Register::R1, 1), // x3011
+            HALT,                                          // x3012
+        ],
+    );
+
+    assert_eq!(vm.run(), Ok(6));
+    /* each HALT's own address, and R0 as it left it */
+    assert_eq!(vm.halt_checkpoints(), [(0x3001, 1), (0x3012, 6)]);
+    assert_eq!(vm.get_register(Register::R1), 1);
+    assert_eq!(printed(&output), "HALT\nHALT\n");
+
+    vm.reset();
+    assert!(vm.halt_checkpoints().is_empty());
+}
+
+#[test]
+fn halts_are_not_recorded_without_continue_past_halt() {
+    let (mut vm, _) = machine(&[HALT]);
+    vm.run().unwrap();
+    assert!(vm.halt_checkpoints().is_empty());
+}
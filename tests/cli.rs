@@ -0,0 +1,338 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/* runs the lc3 binary with `args`, feeding `stdin` to it */
+fn lc3(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lc3-vm"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("lc3 runs");
+
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/* an object image: big-endian origin, then the words */
+fn image(origin: u16, words: &[u16]) -> Vec<u8> {
+    std::iter::once(origin)
+        .chain(words.iter().copied())
+        .flat_map(u16::to_be_bytes)
+        .collect()
+}
+
+#[test]
+fn dash_reads_the_image_from_stdin() {
+    let out = lc3(&["-"], &image(0x3000, &lc3_vm::programs::hello_world()));
+
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "Hello, World!\nHALT\n"
+    );
+}
+
+#[test]
+fn hello_example_prints_its_greeting() {
+    let out = lc3(&["--example", "hello"], b"");
+
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "Hello, World!\nHALT\n"
+    );
+}
+
+#[test]
+fn example_flag_runs_a_built_in_program() {
+    let out = lc3(&["--example", "fib"], b"");
+
+    assert!(out.status.success());
+    assert!(String::from_utf8_lossy(&out.stdout).starts_with("0\n1\n1\n2\n3\n5\n"));
+}
+
+#[test]
+fn unknown_example_lists_the_available_ones() {
+    let out = lc3(&["--example", "nope"], b"");
+
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("hello, fib"));
+}
+
+/* AND R0, R0, #0; ADD R0, R0, #value; HALT */
+fn exit_with(value: u16) -> Vec<u8> {
+    image(0x3000, &[0x5020, 0x1020 | value & 0x1F, 0xF025])
+}
+
+#[test]
+fn exit_code_flag_exits_with_r0() {
+    assert_eq!(
+        lc3(&["--exit-code", "-"], &exit_with(7)).status.code(),
+        Some(7)
+    );
+    assert_eq!(lc3(&["-"], &exit_with(7)).status.code(), Some(0));
+}
+
+#[test]
+fn exit_code_saturates_at_255() {
+    // R0 = -1 = xFFFF
+    assert_eq!(
+        lc3(&["--exit-code", "-"], &exit_with(0x1F)).status.code(),
+        Some(255)
+    );
+}
+
+/* writes a .hex text image to a fresh temp file and returns its path */
+fn hex_file(name: &str, text: &str) -> String {
+    let path = std::env::temp_dir().join(format!("lc3-cli-{}-{}.hex", std::process::id(), name));
+    std::fs::write(&path, text).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+#[test]
+fn getc_reads_piped_stdin_without_a_terminal() {
+    // GETC; OUT; GETC; OUT; HALT
+    let program = hex_file("getc", "xF020\nxF021\nxF020\nxF021\nxF025\n");
+    let out = lc3(&[&program], b"hi");
+    std::fs::remove_file(&program).unwrap();
+
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "hiHALT\n");
+}
+
+#[test]
+fn crlf_is_ignored_when_stdout_is_piped() {
+    let out = lc3(&["--crlf", "--example", "hello"], b"");
+
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "Hello, World!\nHALT\n"
+    );
+}
+
+/* a `.hex` image assembled from one instruction per line at x3000 */
+fn assembled(name: &str, lines: &[&str]) -> String {
+    let words: Vec<String> = lines
+        .iter()
+        .map(|line| match line.strip_prefix(".FILL ") {
+            Some(word) => word.to_string(),
+            None => format!("x{:04X}", lc3_vm::asm_one(line).unwrap()),
+        })
+        .collect();
+    hex_file(name, &words.join("\n"))
+}
+
+#[test]
+fn kbsr_polls_see_piped_keys() {
+    // polls until a key is ready, then exits with it
+    let program = assembled(
+        "poll_key",
+        &[
+            "LDI R0, #3",
+            "BRzp #-2",
+            "LDI R0, #2",
+            "HALT",
+            ".FILL xFE00",
+            ".FILL xFE02",
+        ],
+    );
+    let out = lc3(&["--exit-code", &program], b"A");
+    std::fs::remove_file(&program).unwrap();
+
+    assert_eq!(out.status.code(), Some(65));
+}
+
+#[test]
+fn the_debugger_prompt_reads_stdin_after_a_kbsr_poll() {
+    // LDI R0, #1; HALT; .FILL xFE00
+    let program = hex_file("debug_poll", "xA001\nxF025\nxFE00\n");
+    let out = lc3(&["--debug", &program], b"s\n\nq\n");
+    std::fs::remove_file(&program).unwrap();
+
+    assert!(out.status.success());
+}
+
+#[test]
+fn disassemble_lists_an_image_from_its_origin_without_running_it() {
+    // LEA R0, #2; PUTS; HALT; 'H', 0
+    let path = std::env::temp_dir().join(format!("lc3-cli-{}-listing.obj", std::process::id()));
+    std::fs::write(
+        &path,
+        image(0x4000, &[0xE002, 0xF022, 0xF025, 0x0048, 0x0000]),
+    )
+    .unwrap();
+    let out = lc3(&["--disassemble", &path.to_string_lossy()], b"");
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(out.status.success());
+    let listing = String::from_utf8_lossy(&out.stdout);
+    let lines: Vec<&str> = listing.lines().collect();
+    /* nothing but the listing: the program did not run */
+    assert_eq!(lines.len(), 5, "{}", listing);
+    assert!(lines[0].starts_with("x4000  E002  LEA R0"), "{}", listing);
+    assert!(lines[1].contains("PUTS"));
+    assert!(lines[2].contains("HALT"));
+    assert!(lines[3].starts_with("x4003  0048  .FILL x0048 ; 'H'"));
+}
+
+#[cfg(feature = "cache")]
+#[test]
+fn cache_flag_keeps_a_parsed_copy_and_runs_from_it() {
+    let path = std::env::temp_dir().join(format!("lc3-cli-{}-cached.obj", std::process::id()));
+    let path = path.to_string_lossy().into_owned();
+    let cache = format!("{}.cache", path);
+    std::fs::write(&path, exit_with(9)).unwrap();
+
+    let first = lc3(&["--cache", "--exit-code", &path], b"");
+    let cached = std::fs::metadata(&cache).is_ok();
+    let second = lc3(&["--cache", "--exit-code", &path], b"");
+    std::fs::remove_file(&cache).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(cached);
+    assert_eq!(first.status.code(), Some(9));
+    assert_eq!(second.status.code(), Some(9));
+}
+
+#[test]
+fn dump_on_halt_prints_the_registers_to_stderr() {
+    let out = lc3(&["--dump-on-halt", "-"], &exit_with(5));
+
+    assert!(out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("R0   x0005\n"), "{}", stderr);
+    assert!(stderr.contains("PC   x3003\n"), "{}", stderr);
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "HALT\n");
+}
+
+#[test]
+fn dump_on_halt_also_dumps_after_an_error() {
+    // ADD R0, R0, #7; then the reserved opcode
+    let out = lc3(&["--dump-on-halt", "-"], &image(0x3000, &[0x1027, 0xD000]));
+
+    assert_eq!(out.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("R0   x0007\n"), "{}", stderr);
+}
+
+#[test]
+fn registers_are_not_dumped_without_the_flag() {
+    let out = lc3(&["-"], &exit_with(5));
+
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8_lossy(&out.stderr), "");
+}
+
+#[test]
+fn piped_runs_emit_no_terminal_control_bytes() {
+    // IN; OUT; PUTS of "a\nb"; HALT
+    let program = hex_file(
+        "no_tty",
+        "xF023\nxF021\nxE002\nxF022\nxF025\nx0061\nx000A\nx0062\nx0000\n",
+    );
+    let out = lc3(&[&program], b"k");
+    std::fs::remove_file(&program).unwrap();
+
+    assert!(out.status.success());
+    let stdout = &out.stdout;
+    assert!(!stdout.contains(&0x1B), "escape sequence in {:?}", stdout);
+    assert!(!stdout.contains(&b'\r'), "carriage return in {:?}", stdout);
+    assert!(String::from_utf8_lossy(stdout).ends_with("kka\nbHALT\n"));
+}
+
+#[test]
+fn a_run_stopped_by_max_cycles_resumes_to_completion() {
+    // prints A, B and C, one per loop iteration
+    let program = assembled(
+        "partial",
+        &[
+            "LD R1, #6",
+            "LD R0, #6",
+            "OUT",
+            "ADD R0, R0, #1",
+            "ADD R1, R1, #-1",
+            "BRp #-4",
+            "HALT",
+            ".FILL x0003",
+            ".FILL x0041",
+        ],
+    );
+    let saved = std::env::temp_dir().join(format!("lc3-cli-{}-partial.snap", std::process::id()));
+    let saved = saved.to_string_lossy();
+
+    let first = lc3(
+        &["--max-cycles", "6", "--save-on-exit", &saved, &program],
+        b"",
+    );
+    std::fs::remove_file(&program).unwrap();
+    assert!(first.status.success());
+    assert_eq!(String::from_utf8_lossy(&first.stdout), "A");
+    assert!(
+        String::from_utf8_lossy(&first.stderr).contains("stopped after 6 instructions at x3002")
+    );
+
+    let rest = lc3(&["--exit-code", "--resume", &saved], b"");
+    std::fs::remove_file(&*saved).unwrap();
+    assert_eq!(String::from_utf8_lossy(&rest.stdout), "BCHALT\n");
+    assert_eq!(rest.status.code(), Some(i32::from(b'D')));
+}
+
+#[test]
+fn a_halted_snapshot_is_not_resumed() {
+    let saved = std::env::temp_dir().join(format!("lc3-cli-{}-halted.snap", std::process::id()));
+    let saved = saved.to_string_lossy();
+
+    let first = lc3(&["--example", "hello", "--save-on-exit", &saved], b"");
+    assert!(first.status.success());
+
+    let again = lc3(&["--resume", &saved], b"");
+    std::fs::remove_file(&*saved).unwrap();
+    assert_eq!(again.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&again.stdout), "");
+    assert!(String::from_utf8_lossy(&again.stderr).contains("the program had already halted"));
+}
+
+#[test]
+fn max_cycles_needs_a_number() {
+    let out = lc3(&["--max-cycles", "lots", "--example", "hello"], b"");
+    assert_eq!(out.status.code(), Some(2));
+}
+
+#[test]
+fn tee_logs_the_same_bytes_it_prints() {
+    let log = std::env::temp_dir().join(format!("lc3-cli-{}-session.log", std::process::id()));
+    let log = log.to_string_lossy();
+    let out = lc3(&["--tee", &log, "--example", "hello"], b"");
+
+    assert!(out.status.success());
+    let logged = std::fs::read(&*log).unwrap();
+    std::fs::remove_file(&*log).unwrap();
+    assert_eq!(logged, out.stdout);
+    assert_eq!(logged, b"Hello, World!\nHALT\n");
+}
+
+#[test]
+fn load_failures_explain_what_to_do() {
+    let missing = std::env::temp_dir().join("lc3-cli-no-such-image.obj");
+    let out = lc3(&[&missing.to_string_lossy()], b"");
+    assert_eq!(out.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("file not found"), "{}", stderr);
+    assert!(
+        stderr.contains("hint: check the path for typos"),
+        "{}",
+        stderr
+    );
+
+    let out = lc3(&[&std::env::temp_dir().to_string_lossy()], b"");
+    assert_eq!(out.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("is a directory"), "{}", stderr);
+    assert!(
+        stderr.contains("hint: name an image file inside the directory"),
+        "{}",
+        stderr
+    );
+}